@@ -1,12 +1,22 @@
 use std::collections::HashMap;
 use std::convert::Infallible;
-use warp::Filter;
-use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use warp::{Filter, Reply};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::Value;
 use xlsxwriter::*;
 use xlsxwriter::prelude::*;
-use log::{info, error};
+use xlsxwriter::worksheet::table::{TableColumn, TableOptions};
+use xlsxwriter::worksheet::conditional_format::{ConditionalFormat, ConditionalFormatRuleTypes};
+use log::{debug, info, error};
+use base64::Engine;
+use rayon::prelude::*;
 use csv::Reader;
+use chrono::{Datelike, Timelike};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures_util::{Stream, StreamExt};
 
 #[derive(Deserialize, Debug)]
 struct ExportRequest {
@@ -14,21 +24,312 @@ struct ExportRequest {
     options: ExportOptions,
 }
 
+// Request untuk /generate-excel-from-url: data diambil dari URL (harus JSON array of records)
 #[derive(Deserialize, Debug)]
+struct UrlExportRequest {
+    url: String,
+    options: ExportOptions,
+}
+
+#[derive(Deserialize, Debug)]
+struct MultiSheetRequest {
+    sheets: Vec<SheetSpec>,
+    options: ExportOptions,
+}
+
+// Request untuk /generate-excel-batch: beberapa file terpisah dalam satu ZIP
+#[derive(Deserialize, Debug)]
+struct BatchRequest {
+    files: Vec<ExportRequest>,
+    filename: Option<String>, // Nama file ZIP, default "export.zip"
+}
+
+// Request untuk /generate-excel-template: workbook .xlsx yang sudah ada (base64) diisi data
+#[derive(Deserialize, Debug)]
+struct TemplateRequest {
+    template_base64: String,
+    data: Vec<Value>,
+    options: ExportOptions,
+}
+
+#[derive(Deserialize, Debug)]
+struct SheetSpec {
+    sheet_name: Option<String>,
+    data: Vec<Value>,
+    headers: Option<Vec<String>>,
+    tab_color: Option<String>, // Override options.tab_color untuk sheet ini
+    index: Option<usize>, // Posisi tab (0-based); sheet tanpa index mengisi posisi sisanya sesuai urutan array
+}
+
+// Query params untuk /generate-excel-stream (body berisi NDJSON, bukan ExportRequest)
+#[derive(Deserialize, Debug, Default)]
+struct StreamParams {
+    filename: Option<String>,
+    sheet_name: Option<String>,
+}
+
+// Query params untuk /preview
+#[derive(Deserialize, Debug, Default)]
+struct PreviewParams {
+    rows: Option<usize>, // Jumlah record yang ditampilkan, default 20
+}
+
+// Query params untuk /generate-excel; encoding=base64 membungkus file dalam JSON,
+// meta=header menambahkan X-Records-Processed dan X-Processing-Time-Ms, async=true menjalankan generate di background
+#[derive(Deserialize, Debug, Default)]
+struct GenerateParams {
+    encoding: Option<String>,
+    meta: Option<String>,
+    #[serde(rename = "async")]
+    run_async: Option<bool>,
+}
+
+#[derive(Deserialize, Debug, Default)]
 struct ExportOptions {
     filename: String,
     sheet_name: Option<String>,
     headers: Option<Vec<String>>, // Custom headers jika ada
+    array_join: Option<String>, // Separator untuk array of scalars, default ", "
+    preserve_order: Option<bool>, // Urutan header sesuai JSON (default true), false = alfabet
+    auto_filter: Option<bool>, // Filter dropdown di header row
+    freeze_header: Option<bool>, // Pin header row saat scroll
+    autofit: Option<bool>, // Lebar kolom otomatis berdasarkan isi
+    allow_empty: Option<bool>, // Izinkan data kosong (hanya header)
+    column_types: Option<HashMap<String, ColumnType>>, // Paksa tipe per kolom, mis. {"nip": "text"}
+    column_formats: Option<HashMap<String, String>>, // Number format per kolom, mis. {"price": "$#,##0.00"}
+    header_style: Option<HeaderStyle>, // Style header, default bold + abu-abu + border tipis
+    truncate_long_strings: Option<bool>, // Potong string > 32767 karakter, default error
+    as_table: Option<bool>, // Jadikan range data Excel Table (banded rows + filter di header)
+    freeze_rows: Option<u32>, // Jumlah baris teratas yang di-freeze (override freeze_header)
+    freeze_cols: Option<u16>, // Jumlah kolom kiri yang di-freeze, mis. kolom ID
+    cell_borders: Option<bool>, // Border tipis di semua cell data
+    bool_format: Option<(String, String)>, // Label boolean sebagai teks, mis. ["Ya", "Tidak"]
+    null_value: Option<String>, // Placeholder untuk null / key yang tidak ada, mis. "-"
+    format: Option<OutputFormat>, // "xlsx" (default) atau "ods"
+    detect_urls: Option<bool>, // String http(s):// / mailto: ditulis sebagai hyperlink
+    parallel: Option<bool>, // Konversi row per chunk secara paralel (rayon)
+    header_aliases: Option<HashMap<String, String>>, // Judul kolom yang ditampilkan, mis. {"first_nm": "First Name"}
+    comments_field: Option<String>, // Key berisi {"kolom": "teks comment"} per record, tidak ikut di-export
+    merge_column: Option<String>, // Gabungkan value berurutan yang sama di kolom ini (merge vertikal)
+    normalize_arrays: Option<String>, // Field array of objects yang dipecah jadi satu row per element
+    title: Option<String>, // Judul di atas header (merge selebar semua kolom)
+    default_column_width: Option<f64>, // Lebar kolom kalau autofit tidak aktif, default 15
+    column_widths: Option<HashMap<String, f64>>, // Lebar per kolom, mis. {"description": 60}
+    round_decimals: Option<HashMap<String, u32>>, // Bulatkan float per kolom ke N desimal, mis. {"price": 2}
+    tab_color: Option<String>, // Warna tab sheet (hex), mis. "FF0000"
+    non_finite_as: Option<String>, // Teks untuk NaN/Infinity, mis. "#NUM!"; default cell kosong
+    lock_header_and_id: Option<String>, // Freeze header row + kolom sampai kolom ID ini
+    summaries: Option<HashMap<String, Aggregate>>, // Footer row bold dengan agregat per kolom, mis. {"amount": "sum"}
+    rtl: Option<bool>, // Layout kolom kanan-ke-kiri (Arab/Ibrani)
+    start_row: Option<u32>, // Row awal blok data (0-based), mis. 2 untuk mulai di B3
+    start_col: Option<u16>, // Kolom awal blok data (0-based), mis. 1 untuk mulai di B3
+    conditional_formats: Option<Vec<CondFormatSpec>>, // Highlight / color scale per kolom, mis. angka < 0 merah
+    protect: Option<ProtectOptions>, // Worksheet protection (bukan enkripsi file)
+    formula_columns: Option<HashMap<String, String>>, // Formula per kolom, {row} = nomor row Excel, mis. {"total": "=B{row}*C{row}"}
+    unpivot: Option<UnpivotSpec>, // Wide to long: value_columns dipecah jadi pasangan variable/value, satu row per kolom
+    locale: Option<String>, // Mis. "id-ID" / "de-DE": angka diberi pemisah ribuan (simbol mengikuti regional setting Excel)
+    schema: Option<Value>, // JSON Schema untuk setiap record; pelanggaran ditolak dengan 422
+    image_columns: Option<Vec<String>>, // Kolom berisi gambar base64 / data URI yang di-embed ke cell
+    date_format: Option<String>, // Number format cell tanggal, default "yyyy-mm-dd"
+    datetime_format: Option<String>, // Number format cell tanggal + jam, default "yyyy-mm-dd hh:mm:ss"
+    coerce_numeric_strings: Option<bool>, // String angka seperti "42" / "-3.5" ditulis sebagai angka, default false
+    column_fonts: Option<HashMap<String, FontSpec>>, // Font cell data per kolom, mis. {"kode": {"name": "Consolas"}}
+    row_height: Option<f64>, // Tinggi setiap row data (point), default tinggi Excel 15
+    header_row_height: Option<f64>, // Tinggi header row (point)
+    wrap_columns: Option<Vec<String>>, // Kolom yang teksnya di-wrap di dalam cell, mis. ["notes"]
+    header_groups: Option<Vec<HeaderGroup>>, // Row grup di atas header, mis. "Q1" di-merge di atas jan..mar
+    numeric_columns: Option<Vec<String>>, // Kolom yang string-nya di-parse jadi angka, mis. ["amount"] untuk "1500.00"
+    dedupe_headers: Option<bool>, // Judul kolom kembar diberi suffix ("name", "name_2"), default ditolak 400
+    recalc_on_load: Option<bool>, // Excel menghitung ulang semua formula saat file dibuka, default true
+    complex_value_mode: Option<ComplexMode>, // Nested object: "placeholder" ([Object], default), "json", atau "flatten"
+    on_row_overflow: Option<RowOverflow>, // Data melebihi batas row Excel: "error" (default) atau "split" ke sheet berikutnya
+    include_dictionary: Option<bool>, // Tambah sheet "Dictionary": nama, tipe, dan fill rate setiap kolom
+    hidden_columns: Option<Vec<String>>, // Kolom yang tetap ditulis tapi disembunyikan, mis. kolom bantu formula
+    long_numbers_as_text: Option<bool>, // Integer lebih dari 15 digit (NIP, NIK, nomor rekening) ditulis sebagai teks, default true
+    column_alignment: Option<HashMap<String, Alignment>>, // Perataan horizontal cell data per kolom, mis. {"amount": "right"}
+    skip_invalid_rows: Option<bool>, // Record yang bukan object / melanggar schema dibuang (index di X-Skipped-Rows), bukan 422
+    properties: Option<DocProperties>, // Metadata file (File > Info): title, author, company, subject, keywords
+    sparkline_columns: Option<Vec<SparklineSpec>>, // Mini line chart per row dari field array angka, mis. [{"field": "trend", "column": "trend"}]
+}
+
+// Format file output untuk generate-excel / generate-excel-multi
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    #[default]
+    Xlsx,
+    Ods,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Xlsx => "xlsx",
+            OutputFormat::Ods => "ods",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            OutputFormat::Xlsx => XLSX_CONTENT_TYPE,
+            OutputFormat::Ods => ODS_CONTENT_TYPE,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+struct HeaderStyle {
+    bold: Option<bool>,
+    bg_color: Option<String>, // Hex, mis. "FF0000"
+    font_color: Option<String>,
+    border: Option<bool>,
+}
+
+// Document properties workbook; field yang kosong dibiarkan seperti aslinya
+#[derive(Deserialize, Debug, Clone, Default)]
+struct DocProperties {
+    title: Option<String>,
+    author: Option<String>, // Juga dipakai sebagai "last modified by"
+    company: Option<String>,
+    subject: Option<String>,
+    keywords: Option<String>,
+}
+
+// Sparkline per row: value array angka di field ditulis ke helper cell tersembunyi, chart-nya di cell column
+#[derive(Deserialize, Debug, Clone)]
+struct SparklineSpec {
+    field: String, // Key record berisi array angka, mis. [3, 5, 4]
+    column: String, // Kolom export tempat sparkline digambar; boleh sama dengan field
+}
+
+// Font cell data; field yang kosong mengikuti font default workbook (Calibri 11)
+#[derive(Deserialize, Debug, Clone, Default)]
+struct FontSpec {
+    name: Option<String>,
+    size: Option<f64>, // Point
+    bold: Option<bool>,
+    italic: Option<bool>,
+}
+
+// Satu label grup di atas header: span kolom berurutan mulai dari start_column
+#[derive(Deserialize, Debug, Clone)]
+struct HeaderGroup {
+    label: String,
+    start_column: String, // Key header pertama dalam grup
+    span: usize,
+}
+
+// Spec unpivot (melt): id_columns disalin ke setiap row hasil
+#[derive(Deserialize, Debug, Clone)]
+struct UnpivotSpec {
+    id_columns: Vec<String>,
+    value_columns: Option<Vec<String>>, // Default semua kolom selain id_columns
+    variable_name: Option<String>, // Default "variable"
+    value_name: Option<String>, // Default "value"
+}
+
+// Worksheet protection: semua cell terkunci dari edit. Ini bukan enkripsi, file tetap bisa dibuka
+// siapa saja dan password hanya mencegah unprotect lewat UI Excel
+#[derive(Deserialize, Debug, Clone, Default)]
+struct ProtectOptions {
+    password: Option<String>, // Kosong = unprotect tanpa password
+    allow_select: Option<bool>, // Cell tetap bisa dipilih / di-copy, default true
+    allow_sort: Option<bool>,
+    allow_autofilter: Option<bool>,
+    allow_format_columns: Option<bool>, // Ubah lebar kolom
+    allow_format_rows: Option<bool>,
+    allow_insert_rows: Option<bool>,
+    allow_delete_rows: Option<bool>,
+}
+
+// Conditional format untuk range data satu kolom
+#[derive(Deserialize, Debug, Clone)]
+struct CondFormatSpec {
+    column: String,
+    rule: CondRule,
+    value: Option<f64>, // Threshold greater_than / less_than, batas bawah between
+    max_value: Option<f64>, // Batas atas between
+    color: String, // Fill hex cell yang cocok; untuk color_scale warna nilai maksimum
+    min_color: Option<String>, // Warna nilai minimum color_scale, default putih
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum CondRule {
+    GreaterThan,
+    LessThan,
+    Between,
+    ColorScale,
 }
 
 #[derive(Serialize)]
 struct ApiResponse {
     success: bool,
     message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<String>,
     records_processed: Option<usize>,
     processing_time_ms: Option<u128>,
 }
 
+// Response 202 untuk async=true
+#[derive(Serialize)]
+struct JobAcceptedResponse {
+    job_id: String,
+    progress_url: String,
+}
+
+// Response untuk encoding=base64, bagi gateway yang tidak bisa meneruskan binary
+#[derive(Serialize)]
+struct Base64FileResponse {
+    filename: String,
+    content_base64: String,
+    records_processed: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    skipped_rows: Option<Vec<usize>>, // Index data (0-based) yang dibuang skip_invalid_rows
+}
+
+// Hasil /inspect: skema kolom yang akan dipakai saat generate
+#[derive(Serialize)]
+struct InspectResponse {
+    records: usize,
+    columns: Vec<ColumnReport>,
+}
+
+#[derive(Serialize)]
+struct ColumnReport {
+    name: String,
+    inferred_type: &'static str, // string, integer, float, bool, date, datetime, mixed, atau empty
+    null_count: usize, // null eksplisit + key yang tidak ada
+    sample_value: Option<Value>, // Value non-null pertama
+}
+
+impl ColumnReport {
+    fn new(name: &str) -> Self {
+        ColumnReport {
+            name: name.to_string(),
+            inferred_type: "empty",
+            null_count: 0,
+            sample_value: None,
+        }
+    }
+
+    // value = value asli di record, cell = hasil konversi; null tidak ikut menentukan tipe
+    // (null_value bisa menjadikannya string)
+    fn add(&mut self, value: &Value, cell: &CellValue) {
+        if value.is_null() {
+            self.null_count += 1;
+            return;
+        }
+        if self.sample_value.is_none() {
+            self.sample_value = Some(value.clone());
+        }
+        if let Some(cell_type) = cell_type_name(cell) {
+            self.inferred_type = merge_column_type(self.inferred_type, cell_type);
+        }
+    }
+}
+
 #[derive(Serialize)]
 struct HealthResponse {
     status: String,
@@ -36,439 +337,6398 @@ struct HealthResponse {
     version: String,
 }
 
-// Main handler untuk generate Excel
-async fn generate_excel_handler(req: ExportRequest) -> Result<impl warp::Reply, warp::Rejection> {
-    let start_time = std::time::Instant::now();
-    
-    info!("🦀 Starting Excel generation for {} records", req.data.len());
-    
-    match generate_excel_file(req).await {
-        Ok(excel_data) => {
-            let duration = start_time.elapsed();
-            info!("✅ Excel generated successfully in {:?}", duration);
-            
-            Ok(warp::reply::with_header(
-                excel_data,
-                "content-type",
-                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
-            ))
-        }
-        Err(e) => {
-            error!("❌ Excel generation failed: {}", e);
-            Err(warp::reject::custom(ExcelError::GenerationFailed(e.to_string())))
+// Response /health/deep: "ok" atau "degraded" beserta penyebabnya
+#[derive(Serialize)]
+struct DeepHealthResponse {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<String>,
+    temp_dir: String,
+}
+
+// Konteks per request: request ID (UUID) untuk label log, flag X-Debug, dan job async yang progress-nya diisi.
+// Task-local di async handler, thread-local di blocking pool
+#[derive(Debug, Clone)]
+struct RequestContext {
+    id: String,
+    debug: bool, // X-Debug: true, log debug request ini ikut ditulis walaupun level global lebih tinggi
+    job: Option<std::sync::Arc<GenerationJob>>, // Some di background task async=true
+}
+
+tokio::task_local! {
+    static REQUEST_CONTEXT: RequestContext;
+}
+
+thread_local! {
+    static BLOCKING_REQUEST_CONTEXT: std::cell::RefCell<Option<RequestContext>> = const { std::cell::RefCell::new(None) };
+}
+
+fn current_request_context() -> Option<RequestContext> {
+    REQUEST_CONTEXT
+        .try_with(|context| context.clone())
+        .ok()
+        .or_else(|| BLOCKING_REQUEST_CONTEXT.with(|context| context.borrow().clone()))
+}
+
+fn current_request_id() -> Option<String> {
+    current_request_context().map(|context| context.id)
+}
+
+fn request_debug_enabled() -> bool {
+    REQUEST_CONTEXT
+        .try_with(|context| context.debug)
+        .ok()
+        .or_else(|| BLOCKING_REQUEST_CONTEXT.with(|context| context.borrow().as_ref().map(|context| context.debug)))
+        .unwrap_or(false)
+}
+
+// Logger global: filter RUST_LOG seperti biasa, ditambah log debug dari request dengan X-Debug.
+// Level global tidak berubah; request lain tetap mengikuti RUST_LOG
+struct RequestAwareLogger {
+    default: env_logger::Logger,
+    debug: env_logger::Logger,
+}
+
+impl log::Log for RequestAwareLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.default.enabled(metadata) || (metadata.level() <= log::Level::Debug && request_debug_enabled())
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.default.matches(record) {
+            self.default.log(record);
+        } else if record.level() <= log::Level::Debug && request_debug_enabled() {
+            self.debug.log(record);
         }
     }
+
+    fn flush(&self) {
+        self.default.flush();
+    }
 }
 
-// Core function untuk generate Excel
-async fn generate_excel_file(req: ExportRequest) -> anyhow::Result<Vec<u8>> {
-    let sheet_name = req.options.sheet_name.unwrap_or_else(|| "Sheet1".to_string());
-    
-    // Create workbook - temporarily write to file
-    let temp_file = format!("/tmp/temp_{}.xlsx", uuid::Uuid::new_v4());
-    
-    info!("📝 Creating workbook with sheet: {}", sheet_name);
-    let workbook = Workbook::new(&temp_file)?;
-    let mut worksheet = workbook.add_worksheet(Some(&sheet_name))?;
-    
-    // Auto-detect headers atau gunakan custom headers
-    let headers = if let Some(custom_headers) = req.options.headers {
-        custom_headers
-    } else {
-        auto_detect_headers(&req.data)
-    };
-    
-    info!("📊 Detected {} columns: {:?}", headers.len(), headers);
-    
-    // Create header format
-    let mut header_format = Format::new();
-    header_format.set_bold();
-    header_format.set_bg_color(FormatColor::Custom(0xE0E0E0));
-    header_format.set_border(FormatBorder::Thin);
-    
-    // Write headers
-    for (col, header) in headers.iter().enumerate() {
-        worksheet.write_string(0, col as u16, header, Some(&header_format))?;
+// Baris log yang terjadi di dalam request diberi request_id
+fn format_log_line(buf: &mut env_logger::fmt::Formatter, record: &log::Record) -> std::io::Result<()> {
+    let timestamp = buf.timestamp();
+    match current_request_id() {
+        Some(request_id) => writeln!(
+            buf,
+            "[{} {:<5} {} request_id={}] {}",
+            timestamp,
+            record.level(),
+            record.target(),
+            request_id,
+            record.args()
+        ),
+        None => writeln!(buf, "[{} {:<5} {}] {}", timestamp, record.level(), record.target(), record.args()),
     }
-    
-    // Set column widths
-    for col in 0..headers.len() {
-        worksheet.set_column(col as u16, col as u16, 15.0, None)?;
+}
+
+fn init_logger() {
+    let default = env_logger::Builder::from_default_env().format(format_log_line).build();
+    let debug = env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Debug)
+        .format(format_log_line)
+        .build();
+    // max_level minimal Debug supaya debug!() sampai ke logger; enabled() yang menyaring per request
+    log::set_max_level(default.filter().max(log::LevelFilter::Debug));
+    if log::set_logger(Box::leak(Box::new(RequestAwareLogger { default, debug }))).is_err() {
+        eprintln!("Logger already initialized");
     }
-    
-    // Write data rows (optimized batch processing)
-    info!("📝 Writing {} data rows...", req.data.len());
-    
-    // Process data in chunks for better memory management
-    let chunk_size = std::env::var("EXCEL_CHUNK_SIZE")
-        .unwrap_or_else(|_| "5000".to_string())
-        .parse::<usize>()
-        .unwrap_or(5000);
-    let total_rows = req.data.len();
-    
-    info!("🔧 Using chunk size: {} for {} total rows", chunk_size, total_rows);
-    
-    for chunk_start in (0..total_rows).step_by(chunk_size) {
-        let chunk_end = std::cmp::min(chunk_start + chunk_size, total_rows);
-        let chunk = &req.data[chunk_start..chunk_end];
-        
-        // Pre-process chunk untuk type detection
-        let processed_chunk: Vec<Vec<CellValue>> = chunk
-            .iter()
-            .map(|record| json_to_excel_row_optimized(record, &headers))
-            .collect();
-        
-        // Write chunk ke Excel
-        for (chunk_row_idx, excel_row) in processed_chunk.iter().enumerate() {
-            let row_num = (chunk_start + chunk_row_idx + 1) as u32;
-            
-            for (col, cell_value) in excel_row.iter().enumerate() {
-                let col_idx = col as u16;
-                match cell_value {
-                    CellValue::Empty => {
-                        worksheet.write_blank(row_num, col_idx, None)?;
-                    },
-                    CellValue::String(s) => {
-                        worksheet.write_string(row_num, col_idx, s, None)?;
-                    },
-                    CellValue::Integer(i) => {
-                        worksheet.write_number(row_num, col_idx, *i as f64, None)?;
-                    },
-                    CellValue::Float(f) => {
-                        worksheet.write_number(row_num, col_idx, *f, None)?;
-                    },
-                    CellValue::Bool(b) => {
-                        worksheet.write_boolean(row_num, col_idx, *b, None)?;
-                    },
-                }
+}
+
+// Jalankan satu request di scope RequestContext-nya: semua log ikut berlabel, response diberi X-Request-Id,
+// dan access log (format sama dengan warp::log) ditulis di sini supaya remote address tetap tercatat
+async fn serve_with_request_id<S>(
+    mut service: S,
+    remote_addr: std::net::SocketAddr,
+    req: warp::http::Request<warp::hyper::Body>,
+) -> Result<warp::http::Response<warp::hyper::Body>, Infallible>
+where
+    S: warp::hyper::service::Service<
+        warp::http::Request<warp::hyper::Body>,
+        Response = warp::http::Response<warp::hyper::Body>,
+        Error = Infallible,
+    >,
+{
+    let request_id = uuid::Uuid::new_v4().to_string();
+    let debug = req
+        .headers()
+        .get("x-debug")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case("true") || v.trim() == "1");
+    REQUEST_CONTEXT
+        .scope(RequestContext { id: request_id.clone(), debug, job: None }, async move {
+            let start = std::time::Instant::now();
+            let method = req.method().clone();
+            let path = req.uri().path().to_string();
+            let version = req.version();
+            let header_text = |name| req.headers().get(name).and_then(|v| v.to_str().ok()).unwrap_or("-").to_string();
+            let (referer, user_agent) = (header_text("referer"), header_text("user-agent"));
+
+            let mut response = service.call(req).await?;
+            info!(
+                target: "excel-service",
+                "{} \"{} {} {:?}\" {} \"{}\" \"{}\" {:?}",
+                remote_addr,
+                method,
+                path,
+                version,
+                response.status().as_u16(),
+                referer,
+                user_agent,
+                start.elapsed(),
+            );
+            if let Ok(value) = warp::http::HeaderValue::from_str(&request_id) {
+                response.headers_mut().insert("x-request-id", value);
             }
-        }
-        
-        // Log progress
-        if chunk_end % 10000 == 0 || chunk_end == total_rows {
-            info!("📈 Progress: {} / {} rows processed", chunk_end, total_rows);
-        }
+            Ok(response)
+        })
+        .await
+}
+
+// Jumlah generation yang sedang berjalan, ditampilkan di /status
+static ACTIVE_JOBS: AtomicUsize = AtomicUsize::new(0);
+
+// Increment ACTIVE_JOBS selama guard hidup; decrement otomatis saat selesai atau error
+struct ActiveJobGuard;
+
+impl ActiveJobGuard {
+    fn start() -> Self {
+        ACTIVE_JOBS.fetch_add(1, Ordering::SeqCst);
+        ActiveJobGuard
     }
-    
-    // Finalize workbook
-    info!("💾 Finalizing workbook...");
-    workbook.close()?;
-    
-    // Read file and return as bytes
-    let excel_data = std::fs::read(&temp_file)?;
-    
-    // Clean up temp file
-    let _ = std::fs::remove_file(&temp_file);
-    
-    info!("✅ Excel file generated, size: {} bytes", excel_data.len());
-    Ok(excel_data)
 }
 
-// Auto-detect headers dari JSON pertama
-fn auto_detect_headers(data: &[Value]) -> Vec<String> {
-    if let Some(first_record) = data.first() {
-        if let Value::Object(map) = first_record {
-            let mut headers: Vec<String> = map.keys().cloned().collect();
-            headers.sort(); // Sort untuk konsistensi
-            return headers;
-        }
+impl Drop for ActiveJobGuard {
+    fn drop(&mut self) {
+        ACTIVE_JOBS.fetch_sub(1, Ordering::SeqCst);
     }
-    vec!["data".to_string()] // Fallback
 }
 
-// Optimized: Convert JSON record ke Excel row dengan type detection
-fn json_to_excel_row_optimized(record: &Value, headers: &[String]) -> Vec<CellValue> {
-    headers.iter().map(|header| {
-        match &record[header] {
-            Value::Null => CellValue::Empty,
-            Value::Bool(b) => CellValue::Bool(*b),
-            Value::Number(n) => CellValue::String(n.to_string()),
-            Value::String(s) => CellValue::String(s.clone()),
-            Value::Array(_) => CellValue::String("[Array]".to_string()),
-            Value::Object(_) => CellValue::String("[Object]".to_string()),
+// Batas atas bucket histogram durasi generate (detik), format Prometheus
+const DURATION_BUCKETS: [f64; 9] = [0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+// Metrics untuk GET /metrics, di-update oleh setiap handler generate
+static METRICS: Metrics = Metrics::new();
+
+struct Metrics {
+    generations: AtomicU64,
+    records_processed: AtomicU64,
+    failures: std::sync::Mutex<std::collections::BTreeMap<&'static str, u64>>, // Per error_code
+    duration_buckets: [AtomicU64; DURATION_BUCKETS.len()], // Non-kumulatif, dijumlahkan saat render
+    duration_sum_micros: AtomicU64,
+}
+
+impl Metrics {
+    const fn new() -> Self {
+        Metrics {
+            generations: AtomicU64::new(0),
+            records_processed: AtomicU64::new(0),
+            failures: std::sync::Mutex::new(std::collections::BTreeMap::new()),
+            duration_buckets: [const { AtomicU64::new(0) }; DURATION_BUCKETS.len()],
+            duration_sum_micros: AtomicU64::new(0),
         }
-    }).collect()
+    }
+
+    fn record_success(&self, duration: std::time::Duration, records: usize) {
+        self.generations.fetch_add(1, Ordering::Relaxed);
+        self.records_processed.fetch_add(records as u64, Ordering::Relaxed);
+        self.duration_sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        let secs = duration.as_secs_f64();
+        if let Some(bucket) = DURATION_BUCKETS.iter().position(|upper| secs <= *upper) {
+            self.duration_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn record_failure(&self, e: &anyhow::Error) {
+        let error_code = e
+            .downcast_ref::<ExcelError>()
+            .map_or("XLSX_WRITE_FAILED", ExcelError::error_code);
+        let mut failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        *failures.entry(error_code).or_insert(0) += 1;
+    }
+
+    // Prometheus text exposition format
+    fn render(&self) -> String {
+        let generations = self.generations.load(Ordering::Relaxed);
+        let mut out = String::new();
+        out.push_str("# HELP excel_generations_total Successful file generations.\n");
+        out.push_str("# TYPE excel_generations_total counter\n");
+        out.push_str(&format!("excel_generations_total {}\n", generations));
+
+        out.push_str("# HELP excel_generation_failures_total Failed file generations by error code.\n");
+        out.push_str("# TYPE excel_generation_failures_total counter\n");
+        let failures = self.failures.lock().unwrap_or_else(|e| e.into_inner());
+        for (error_code, count) in failures.iter() {
+            out.push_str(&format!("excel_generation_failures_total{{error_code=\"{}\"}} {}\n", error_code, count));
+        }
+        drop(failures);
+
+        out.push_str("# HELP excel_records_processed_total Records written by successful generations.\n");
+        out.push_str("# TYPE excel_records_processed_total counter\n");
+        out.push_str(&format!("excel_records_processed_total {}\n", self.records_processed.load(Ordering::Relaxed)));
+
+        out.push_str("# HELP excel_generation_duration_seconds Duration of successful generations.\n");
+        out.push_str("# TYPE excel_generation_duration_seconds histogram\n");
+        let mut cumulative = 0;
+        for (upper, bucket) in DURATION_BUCKETS.iter().zip(&self.duration_buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            out.push_str(&format!("excel_generation_duration_seconds_bucket{{le=\"{}\"}} {}\n", upper, cumulative));
+        }
+        out.push_str(&format!("excel_generation_duration_seconds_bucket{{le=\"+Inf\"}} {}\n", generations));
+        let sum_secs = self.duration_sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        out.push_str(&format!("excel_generation_duration_seconds_sum {}\n", sum_secs));
+        out.push_str(&format!("excel_generation_duration_seconds_count {}\n", generations));
+        out
+    }
 }
 
-// Enum untuk optimized cell values
-#[derive(Debug)]
-enum CellValue {
-    Empty,
-    String(String),
-    Integer(i64),
-    Float(f64),
-    Bool(bool),
+// Batas generate bersamaan dari MAX_CONCURRENT; None kalau tidak di-set (tanpa batas)
+static GENERATION_PERMITS: std::sync::OnceLock<Option<tokio::sync::Semaphore>> = std::sync::OnceLock::new();
+
+// Lama menunggu permit sebelum request ditolak dengan 503
+const PERMIT_WAIT: std::time::Duration = std::time::Duration::from_millis(500);
+
+fn max_concurrent() -> Option<usize> {
+    std::env::var("MAX_CONCURRENT")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|limit| *limit > 0)
 }
 
-// Health check endpoint
-async fn health_handler() -> Result<impl warp::Reply, Infallible> {
-    let response = HealthResponse {
-        status: "healthy".to_string(),
-        service: "excel-service".to_string(),
-        version: env!("CARGO_PKG_VERSION").to_string(),
+// Ambil permit generate; kalau penuh sampai PERMIT_WAIT, tolak dengan SERVICE_BUSY (503 + Retry-After)
+async fn acquire_generation_permit() -> Result<Option<tokio::sync::SemaphorePermit<'static>>, ExcelError> {
+    let Some(semaphore) = GENERATION_PERMITS
+        .get_or_init(|| max_concurrent().map(tokio::sync::Semaphore::new))
+        .as_ref()
+    else {
+        return Ok(None);
     };
-    
-    Ok(warp::reply::json(&response))
+    match tokio::time::timeout(PERMIT_WAIT, semaphore.acquire()).await {
+        Ok(Ok(permit)) => Ok(Some(permit)),
+        _ => Err(ExcelError::ServiceBusy(
+            "Too many concurrent generations (MAX_CONCURRENT), retry later".to_string(),
+        )),
+    }
 }
 
-// CSV to Excel handler
-async fn csv_to_excel_handler(csv_data: String) -> Result<impl warp::Reply, warp::Rejection> {
-    let start_time = std::time::Instant::now();
-    
-    info!("🦀 Starting CSV to Excel conversion");
-    
-    match convert_csv_to_excel(csv_data).await {
-        Ok(excel_data) => {
-            let duration = start_time.elapsed();
-            info!("✅ CSV to Excel conversion completed in {:?}", duration);
-            
-            Ok(warp::reply::with_header(
-                excel_data,
-                "content-type",
-                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
-            ))
+// Cache hasil generate per Idempotency-Key, supaya retry client tidak generate ulang
+static IDEMPOTENCY_CACHE: std::sync::Mutex<IdempotencyCache> = std::sync::Mutex::new(IdempotencyCache {
+    entries: std::collections::VecDeque::new(),
+    total_bytes: 0,
+});
+
+const MAX_IDEMPOTENCY_KEY_LEN: usize = 255;
+
+struct CachedFile {
+    key: String,
+    body_digest: u64, // Hash body request; key yang dipakai ulang dengan body lain ditolak
+    file: GeneratedFile,
+    created: std::time::Instant,
+}
+
+// LRU sederhana: entry paling lama dipakai di depan. Jumlah entry kecil, jadi pencarian linear cukup
+struct IdempotencyCache {
+    entries: std::collections::VecDeque<CachedFile>,
+    total_bytes: usize,
+}
+
+impl IdempotencyCache {
+    fn get(&mut self, key: &str, body_digest: u64) -> Result<Option<GeneratedFile>, ExcelError> {
+        self.evict_expired(idempotency_ttl());
+        let Some(pos) = self.entries.iter().position(|entry| entry.key == key) else {
+            return Ok(None);
+        };
+        if self.entries[pos].body_digest != body_digest {
+            return Err(ExcelError::IdempotencyKeyReused(
+                "Idempotency-Key was already used with a different request body".to_string(),
+            ));
         }
-        Err(e) => {
-            error!("❌ CSV to Excel conversion failed: {}", e);
-            Err(warp::reject::custom(ExcelError::GenerationFailed(e.to_string())))
+        let Some(entry) = self.entries.remove(pos) else {
+            return Ok(None);
+        };
+        let file = entry.file.clone();
+        self.entries.push_back(entry);
+        Ok(Some(file))
+    }
+
+    // File yang lebih besar dari seluruh cache tidak disimpan
+    fn insert(&mut self, key: String, body_digest: u64, file: GeneratedFile, max_bytes: usize) {
+        if file.data.len() > max_bytes {
+            return;
+        }
+        if let Some(pos) = self.entries.iter().position(|entry| entry.key == key) {
+            self.remove(pos);
+        }
+        while self.total_bytes + file.data.len() > max_bytes {
+            self.remove(0);
         }
+        self.total_bytes += file.data.len();
+        self.entries.push_back(CachedFile {
+            key,
+            body_digest,
+            file,
+            created: std::time::Instant::now(),
+        });
     }
-}
 
-// Convert CSV to Excel
-async fn convert_csv_to_excel(csv_content: String) -> anyhow::Result<Vec<u8>> {
-    info!("📝 Parsing CSV content");
-    
-    // Parse CSV
-    let mut reader = Reader::from_reader(csv_content.as_bytes());
-    let mut records = Vec::new();
-    let mut headers = Vec::new();
-    
-    // Get headers
-    if let Ok(header_record) = reader.headers() {
-        headers = header_record.iter().map(|h| h.to_string()).collect();
+    fn evict_expired(&mut self, ttl: std::time::Duration) {
+        self.entries.retain(|entry| entry.created.elapsed() < ttl);
+        self.total_bytes = self.entries.iter().map(|entry| entry.file.data.len()).sum();
     }
-    
-    // Get all records
-    for result in reader.records() {
-        let record = result?;
-        let mut row_data = HashMap::new();
-        
-        for (i, field) in record.iter().enumerate() {
-            if let Some(header) = headers.get(i) {
-                row_data.insert(header.clone(), Value::String(field.to_string()));
-            }
+
+    fn remove(&mut self, pos: usize) {
+        if let Some(entry) = self.entries.remove(pos) {
+            self.total_bytes -= entry.file.data.len();
         }
-        records.push(Value::Object(serde_json::Map::from_iter(row_data)));
     }
-    
-    info!("📊 Parsed {} records with {} columns", records.len(), headers.len());
-    
-    // Create Excel using existing function
-    let req = ExportRequest {
-        data: records,
-        options: ExportOptions {
-            filename: "converted.xlsx".to_string(),
-            sheet_name: Some("Sheet1".to_string()),
-            headers: Some(headers),
-        },
-    };
-    
-    generate_excel_file(req).await
 }
 
-// Test endpoint untuk cek service
-async fn test_handler() -> Result<impl warp::Reply, warp::Rejection> {
-    info!("🧪 Test endpoint called");
-    
-    // Generate sample data with NIP
-    let sample_data = vec![
-        serde_json::json!({
-            "id": 1,
-            "name": "John Doe",
-            "nip": "199103052019031008",
-            "email": "john@example.com",
-            "age": 30,
-            "city": "Jakarta"
-        }),
-        serde_json::json!({
-            "id": 2,
-            "name": "Jane Smith", 
-            "nip": "198712142020121005",
-            "email": "jane@example.com",
-            "age": 25,
-            "city": "Surabaya"
-        })
-    ];
-    
-    let req = ExportRequest {
-        data: sample_data,
-        options: ExportOptions {
-            filename: "test.xlsx".to_string(),
-            sheet_name: Some("Test".to_string()),
-            headers: None,
-        },
-    };
-    
-    generate_excel_handler(req).await
+// Lama file disimpan per Idempotency-Key (default 10 menit)
+fn idempotency_ttl() -> std::time::Duration {
+    let secs = std::env::var("IDEMPOTENCY_TTL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(600);
+    std::time::Duration::from_secs(secs)
 }
 
-// Custom error types
+// Total ukuran file di cache idempotency (default 256MB)
+fn idempotency_cache_bytes() -> usize {
+    std::env::var("IDEMPOTENCY_CACHE_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(256)
+        * 1024
+        * 1024
+}
+
+fn idempotency_cache() -> std::sync::MutexGuard<'static, IdempotencyCache> {
+    IDEMPOTENCY_CACHE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Job generate async (POST /generate-excel?async=true). rows_done diisi write_sheet lewat RequestContext.job.
+// Hasil disimpan sampai di-download sekali (GET /download/:job_id) atau JOB_TTL_SECS setelah selesai
 #[derive(Debug)]
-enum ExcelError {
-    GenerationFailed(String),
+struct GenerationJob {
+    total: AtomicUsize,
+    rows_done: AtomicUsize,
+    // None selama generate masih berjalan, dan setelah hasilnya diambil /download
+    outcome: std::sync::Mutex<Option<Result<JobFile, ExcelError>>>,
+    // Di-set setelah outcome terisi; job yang masih berjalan tidak pernah kedaluwarsa
+    finished: std::sync::OnceLock<std::time::Instant>,
 }
 
-impl warp::reject::Reject for ExcelError {}
+#[derive(Debug)]
+struct JobFile {
+    filename: String,
+    content_type: &'static str,
+    file: GeneratedFile,
+}
 
-// Error handler
-async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, Infallible> {
-    let code;
-    let message;
-
-    if err.is_not_found() {
-        code = warp::http::StatusCode::NOT_FOUND;
-        message = "Not Found";
-    } else if let Some(_) = err.find::<warp::reject::MethodNotAllowed>() {
-        code = warp::http::StatusCode::METHOD_NOT_ALLOWED;
-        message = "Method Not Allowed";
-    } else if let Some(e) = err.find::<ExcelError>() {
-        match e {
-            ExcelError::GenerationFailed(msg) => {
-                code = warp::http::StatusCode::INTERNAL_SERVER_ERROR;
-                message = msg;
+impl GenerationJob {
+    fn progress_event(&self) -> warp::sse::Event {
+        let data = serde_json::json!({
+            "rows_done": self.rows_done.load(Ordering::SeqCst),
+            "total": self.total.load(Ordering::SeqCst),
+        });
+        warp::sse::Event::default().event("progress").data(data.to_string())
+    }
+
+    // Event "done" / "error" kalau job sudah selesai
+    fn finished_event(&self, job_id: &str) -> Option<warp::sse::Event> {
+        self.finished.get()?;
+        let outcome = self.outcome.lock().unwrap_or_else(|e| e.into_inner());
+        let (event, data) = match outcome.as_ref() {
+            // Hasil sudah di-download atau kedaluwarsa selagi stream masih terbuka
+            None => ("error", serde_json::json!({ "error_code": "JOB_NOT_FOUND", "message": "Job was already downloaded or has expired" })),
+            Some(Ok(file)) => (
+                "done",
+                serde_json::json!({
+                    "download_url": format!("/download/{}", job_id),
+                    "filename": file.filename,
+                    "size": file.file.data.len(),
+                    "skipped_rows": file.file.skipped_rows,
+                }),
+            ),
+            Some(Err(e)) => ("error", serde_json::json!({ "error_code": e.error_code(), "message": e.message() })),
+        };
+        Some(warp::sse::Event::default().event(event).data(data.to_string()))
+    }
+}
+
+static JOBS: std::sync::LazyLock<std::sync::Mutex<HashMap<String, std::sync::Arc<GenerationJob>>>> =
+    std::sync::LazyLock::new(Default::default);
+
+fn jobs() -> std::sync::MutexGuard<'static, HashMap<String, std::sync::Arc<GenerationJob>>> {
+    JOBS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// Lama hasil job async disimpan setelah selesai (default 10 menit)
+fn job_ttl() -> std::time::Duration {
+    let secs = std::env::var("JOB_TTL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(600);
+    std::time::Duration::from_secs(secs)
+}
+
+// Total ukuran hasil job async yang menunggu di-download (default 512MB)
+fn job_results_max_bytes() -> usize {
+    std::env::var("JOB_RESULTS_MAX_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(512)
+        * 1024
+        * 1024
+}
+
+// Simpan hasil job dan tandai selesai. Kalau total hasil yang belum di-download melewati max_bytes, hasil job
+// yang paling lama selesai dibuang dulu (download-nya jadi 404); hasil yang sendirian melebihi batas jadi error
+fn finish_job(job: &GenerationJob, outcome: Result<JobFile, ExcelError>, max_bytes: usize) {
+    let outcome = match outcome {
+        Ok(job_file) if job_file.file.data.len() > max_bytes => Err(ExcelError::PayloadTooLarge(format!(
+            "Async result of {} bytes exceeds JOB_RESULTS_MAX_MB ({}MB)",
+            job_file.file.data.len(),
+            max_bytes / 1024 / 1024
+        ))),
+        outcome => outcome,
+    };
+    let mut jobs = jobs();
+    if let Ok(job_file) = &outcome {
+        let mut stored: Vec<(std::time::Instant, String, usize)> = jobs
+            .iter()
+            .filter_map(|(id, job)| {
+                let finished = *job.finished.get()?;
+                let outcome = job.outcome.lock().unwrap_or_else(|e| e.into_inner());
+                let size = outcome.as_ref()?.as_ref().ok()?.file.data.len();
+                Some((finished, id.clone(), size))
+            })
+            .collect();
+        stored.sort();
+        let mut total = stored.iter().map(|(_, _, size)| size).sum::<usize>() + job_file.file.data.len();
+        for (_, id, size) in stored {
+            if total <= max_bytes {
+                break;
             }
+            info!("🧹 Dropping async job {} result ({} bytes) to stay under JOB_RESULTS_MAX_MB", id, size);
+            jobs.remove(&id);
+            total -= size;
         }
-    } else {
-        error!("Unhandled rejection: {:?}", err);
-        code = warp::http::StatusCode::INTERNAL_SERVER_ERROR;
-        message = "Internal Server Error";
     }
+    *job.outcome.lock().unwrap_or_else(|e| e.into_inner()) = Some(outcome);
+    let _ = job.finished.set(std::time::Instant::now());
+}
 
-    let json = warp::reply::json(&ApiResponse {
-        success: false,
-        message: message.to_string(),
-        records_processed: None,
-        processing_time_ms: None,
-    });
+// Dibersihkan setiap ada request job baru, progress, atau download
+fn evict_expired_jobs(ttl: std::time::Duration) {
+    jobs().retain(|_, job| job.finished.get().is_none_or(|finished| finished.elapsed() < ttl));
+}
 
-    Ok(warp::reply::with_status(json, code))
+fn job_not_found(job_id: &str) -> warp::Rejection {
+    warp::reject::custom(ExcelError::JobNotFound(format!(
+        "Job '{}' not found; it may have expired or already been downloaded",
+        job_id
+    )))
 }
 
-// CORS filter
-fn cors() -> warp::filters::cors::Builder {
-    warp::cors()
-        .allow_any_origin()
-        .allow_headers(vec!["content-type", "authorization"])
-        .allow_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
+// Task job async yang di-spawn; ditunggu saat shutdown supaya job tidak terputus di tengah jalan
+static JOB_TASKS: std::sync::Mutex<Vec<tokio::task::JoinHandle<()>>> = std::sync::Mutex::new(Vec::new());
+
+fn track_job_task(handle: tokio::task::JoinHandle<()>) {
+    let mut tasks = JOB_TASKS.lock().unwrap_or_else(|e| e.into_inner());
+    tasks.retain(|task| !task.is_finished());
+    tasks.push(handle);
 }
 
-#[tokio::main]
-async fn main() {
-    // Initialize logger
-    env_logger::init();
-    
-    info!("🚀 Starting Excel Service v{}", env!("CARGO_PKG_VERSION"));
-    
-    // Health check route
-    let health = warp::path("health")
-        .and(warp::get())
-        .and_then(health_handler);
-    
-    // Test route
-    let test = warp::path("test")
-        .and(warp::get())
-        .and_then(test_handler);
-    
-    // Get max body size from environment (default 2GB for 24GB server)
-    let max_body_size = std::env::var("EXCEL_MAX_BODY_SIZE_MB")
-        .unwrap_or_else(|_| "2048".to_string())
-        .parse::<u64>()
-        .unwrap_or(2048) * 1024 * 1024;
+// Dipanggil setelah server berhenti menerima request; tidak ada job baru yang bisa dimulai lagi
+async fn wait_for_job_tasks() {
+    let tasks = std::mem::take(&mut *JOB_TASKS.lock().unwrap_or_else(|e| e.into_inner()));
+    let running: Vec<_> = tasks.into_iter().filter(|task| !task.is_finished()).collect();
+    if running.is_empty() {
+        return;
+    }
+    info!("⏳ Waiting for {} async job(s) to finish...", running.len());
+    for task in running {
+        if let Err(e) = task.await {
+            error!("❌ Async job task failed: {}", e);
+        }
+    }
+}
 
-    // Main Excel generation route
-    let generate = warp::path("generate-excel")
-        .and(warp::post())
-        .and(warp::body::content_length_limit(max_body_size)) // Configurable limit
-        .and(warp::body::json())
-        .and_then(generate_excel_handler);
+fn current_job() -> Option<std::sync::Arc<GenerationJob>> {
+    current_request_context().and_then(|context| context.job)
+}
+
+// Dipanggil setelah setiap chunk ditulis; no-op di luar job async
+fn report_job_progress(rows: usize) {
+    if let Some(job) = current_job() {
+        job.rows_done.fetch_add(rows, Ordering::SeqCst);
+    }
+}
+
+// Jalankan generate di background task. RequestContext request asal ikut dibawa, jadi log job tetap
+// berlabel request_id yang membuatnya dan X-Debug tetap berlaku
+fn start_generation_job(req: ExportRequest, filename: String, content_type: &'static str) -> String {
+    evict_expired_jobs(job_ttl());
+    let job_id = uuid::Uuid::new_v4().to_string();
+    let records_processed = req.data.len();
+    let job = std::sync::Arc::new(GenerationJob {
+        total: AtomicUsize::new(records_processed),
+        rows_done: AtomicUsize::new(0),
+        outcome: std::sync::Mutex::new(None),
+        finished: std::sync::OnceLock::new(),
+    });
+    jobs().insert(job_id.clone(), job.clone());
+    let context = RequestContext {
+        job: Some(job.clone()),
+        ..current_request_context().unwrap_or_else(|| RequestContext { id: job_id.clone(), debug: false, job: None })
+    };
+    info!("🕒 Async job {} started for {} records", job_id, records_processed);
     
-    // CSV to Excel route
-    let csv_to_excel = warp::path("csv-to-excel")
-        .and(warp::post())
-        .and(warp::header::exact("content-type", "text/csv"))
-        .and(warp::body::content_length_limit(max_body_size / 2)) // Half for CSV
-        .and(warp::body::bytes())
-        .map(|bytes: bytes::Bytes| String::from_utf8_lossy(&bytes).to_string())
-        .and_then(csv_to_excel_handler);
+    let task = tokio::spawn(REQUEST_CONTEXT.scope(context, async move {
+        let start_time = std::time::Instant::now();
+        let generated = async {
+            let slot = acquire_generation_slot().await?;
+            GENERATION_SLOT.scope(slot, generate_file_skipping_invalid_rows(req)).await
+        }
+        .await;
+        let outcome = match generated {
+            Ok(file) => {
+                let duration = start_time.elapsed();
+                info!("✅ Async job finished in {:?}", duration);
+                METRICS.record_success(duration, records_processed - file.skipped_count());
+                job.rows_done.store(job.total.load(Ordering::SeqCst), Ordering::SeqCst);
+                Ok(JobFile { filename, content_type, file })
+            }
+            Err(e) => {
+                error!("❌ Async job failed: {}", e);
+                METRICS.record_failure(&e);
+                Err(to_excel_error(e))
+            }
+        };
+        finish_job(&job, outcome, job_results_max_bytes());
+    }));
+    track_job_task(task);
+    job_id
+}
+
+// Interval event progress di SSE
+const JOB_PROGRESS_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+// SSE: event "progress" {rows_done, total} setiap JOB_PROGRESS_INTERVAL, lalu "done" {download_url} atau
+// "error" {error_code, message}, lalu stream ditutup
+async fn job_progress_handler(job_id: String) -> Result<impl warp::Reply, warp::Rejection> {
+    evict_expired_jobs(job_ttl());
+    let job = jobs().get(&job_id).cloned().ok_or_else(|| job_not_found(&job_id))?;
+    let events = futures_util::stream::unfold(Some((job, job_id, false)), |state| async move {
+        let (job, job_id, wait) = state?;
+        if wait {
+            tokio::time::sleep(JOB_PROGRESS_INTERVAL).await;
+        }
+        // Outcome dibaca sebelum progress supaya progress terakhir sebelum "done" sudah mencakup semua row
+        let finished = job.finished_event(&job_id);
+        let mut events = vec![job.progress_event()];
+        let next = match finished {
+            Some(event) => {
+                events.push(event);
+                None
+            }
+            None => Some((job, job_id, true)),
+        };
+        Some((futures_util::stream::iter(events.into_iter().map(Ok::<_, Infallible>)), next))
+    })
+    .flatten();
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(events)))
+}
+
+// File hasil job async, sekali download: job dihapus dari registry sebelum file dikirim, jadi request kedua
+// (atau setelah JOB_TTL_SECS) mendapat 404. Job yang gagal mengembalikan error generate-nya, juga sekali
+async fn job_download_handler(job_id: String) -> Result<impl warp::Reply, warp::Rejection> {
+    evict_expired_jobs(job_ttl());
+    let job = {
+        let mut jobs = jobs();
+        let job = jobs.get(&job_id).ok_or_else(|| job_not_found(&job_id))?;
+        if job.finished.get().is_none() {
+            return Err(warp::reject::custom(ExcelError::JobNotReady(format!(
+                "Job '{}' is still running ({} / {} rows)",
+                job_id,
+                job.rows_done.load(Ordering::SeqCst),
+                job.total.load(Ordering::SeqCst)
+            ))));
+        }
+        jobs.remove(&job_id).ok_or_else(|| job_not_found(&job_id))?
+    };
+    let outcome = job.outcome.lock().unwrap_or_else(|e| e.into_inner()).take();
+    match outcome {
+        Some(Ok(job_file)) => {
+            info!("📥 Async job {} downloaded ({} bytes)", job_id, job_file.file.data.len());
+            let mut response = file_reply(job_file.file.data, job_file.content_type, &job_file.filename).into_response();
+            if let Some(skipped) = &job_file.file.skipped_rows {
+                insert_skipped_rows_headers(response.headers_mut(), skipped);
+            }
+            Ok(response)
+        }
+        Some(Err(e)) => Err(warp::reject::custom(e)),
+        None => Err(job_not_found(&job_id)),
+    }
+}
+
+// Permit MAX_CONCURRENT + hitungan ACTIVE_JOBS untuk satu generate. Setiap blocking task yang di-spawn di dalam
+// GENERATION_SLOT.scope ikut memegang Arc-nya, jadi slot baru dilepas setelah pekerjaan blocking benar-benar
+// selesai, termasuk yang masih berjalan setelah request-nya dijawab 504
+struct GenerationSlot {
+    _permit: Option<tokio::sync::SemaphorePermit<'static>>,
+    _active_job: ActiveJobGuard,
+}
+
+tokio::task_local! {
+    static GENERATION_SLOT: std::sync::Arc<GenerationSlot>;
+}
+
+async fn acquire_generation_slot() -> Result<std::sync::Arc<GenerationSlot>, ExcelError> {
+    let permit = acquire_generation_permit().await?;
+    Ok(std::sync::Arc::new(GenerationSlot {
+        _permit: permit,
+        _active_job: ActiveJobGuard::start(),
+    }))
+}
+
+// Main handler untuk generate Excel
+async fn generate_excel_handler(
+    req: ExportRequest,
+    body_digest: u64,
+    params: GenerateParams,
+    accept_encoding: Option<String>,
+    idempotency_key: Option<String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let start_time = std::time::Instant::now();
     
-    // Status endpoint
-    let status = warp::path("status")
-        .and(warp::get())
-        .map(|| {
-            let response = serde_json::json!({
-                "service": "excel-service",
-                "status": "running",
-                "timestamp": chrono::Utc::now().to_rfc3339(),
-                "memory_usage": get_memory_usage()
-            });
-            warp::reply::json(&response)
-        });
+    let base64_mode = match params.encoding.as_deref() {
+        None => false,
+        Some(encoding) if encoding.eq_ignore_ascii_case("base64") => true,
+        Some(other) => {
+            return Err(warp::reject::custom(ExcelError::InvalidOptions(format!(
+                "Unsupported encoding '{}': only \"base64\" is supported",
+                other
+            ))));
+        }
+    };
+    let meta_headers = match params.meta.as_deref() {
+        None => false,
+        Some(meta) if meta.eq_ignore_ascii_case("header") => true,
+        Some(other) => {
+            return Err(warp::reject::custom(ExcelError::InvalidOptions(format!(
+                "Unsupported meta '{}': only \"header\" is supported",
+                other
+            ))));
+        }
+    };
     
-    // Combine all routes
-    let routes = health
-        .or(test)
-        .or(generate)
-        .or(csv_to_excel)
-        .or(status)
-        .with(cors())
-        .recover(handle_rejection)
-        .with(warp::log("excel-service"));
+    if let Some(key) = &idempotency_key {
+        if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+            return Err(warp::reject::custom(ExcelError::InvalidOptions(format!(
+                "Idempotency-Key must be 1 to {} characters",
+                MAX_IDEMPOTENCY_KEY_LEN
+            ))));
+        }
+    }
     
-    let port = std::env::var("PORT")
-        .unwrap_or_else(|_| "3333".to_string())
-        .parse::<u16>()
-        .unwrap_or(3333);
+    let output_format = req.options.format.unwrap_or_default();
+    let filename = sanitize_filename(&req.options.filename, output_format.extension()).map_err(warp::reject::custom)?;
+    let records_received = req.data.len();
     
-    info!("🦀 Excel Service running on http://0.0.0.0:{}", port);
-    info!("🔧 Configuration:");
-    info!("   - Chunk size: {}", std::env::var("EXCEL_CHUNK_SIZE").unwrap_or("5000".to_string()));
-    info!("   - Max body size: {}MB", max_body_size / (1024 * 1024));
-    info!("   - Memory limit: {}MB", std::env::var("EXCEL_MAX_MEMORY_MB").unwrap_or("6144".to_string()));
-    info!("📋 Available endpoints:");
-    info!("   GET  /health        - Health check");
-    info!("   GET  /test          - Test with sample data");
-    info!("   GET  /status        - Service status");
-    info!("   POST /generate-excel - Generate Excel file");
-    info!("   POST /csv-to-excel  - Convert CSV to Excel (Content-Type: text/csv)");
+    if params.run_async.unwrap_or(false) {
+        if base64_mode || meta_headers || idempotency_key.is_some() {
+            return Err(warp::reject::custom(ExcelError::InvalidOptions(
+                "async=true cannot be combined with encoding, meta or Idempotency-Key".to_string(),
+            )));
+        }
+        let job_id = start_generation_job(req, filename, output_format.content_type());
+        let body = JobAcceptedResponse {
+            progress_url: format!("/generate-excel/progress/{}", job_id),
+            job_id,
+        };
+        return Ok(warp::reply::with_status(warp::reply::json(&body), warp::http::StatusCode::ACCEPTED).into_response());
+    }
     
-    warp::serve(routes)
-        .run(([0, 0, 0, 0], port))
-        .await;
-}
-
-// Helper function untuk get memory usage
-fn get_memory_usage() -> HashMap<String, String> {
-    let mut usage = HashMap::new();
+    // Key yang sama dalam TTL langsung dapat file dari cache, tanpa permit dan tanpa generate ulang
+    let cached = match idempotency_key.as_deref() {
+        Some(key) => idempotency_cache().get(key, body_digest).map_err(warp::reject::custom)?,
+        None => None,
+    };
+    let cache_hit = cached.is_some();
+    let generated = match cached {
+        Some(file) => {
+            info!("♻️ Serving cached file for Idempotency-Key ({} bytes)", file.data.len());
+            Ok(file)
+        }
+        None => {
+            let slot = acquire_generation_slot().await.map_err(warp::reject::custom)?;
+            info!("🦀 Starting Excel generation for {} records", req.data.len());
+            GENERATION_SLOT.scope(slot, generate_file_skipping_invalid_rows(req)).await
+        }
+    };
     
-    #[cfg(target_os = "linux")]
-    {
-        if let Ok(contents) = std::fs::read_to_string("/proc/self/status") {
-            for line in contents.lines() {
-                if line.starts_with("VmRSS:") {
-                    usage.insert("rss".to_string(), line.split_whitespace().nth(1).unwrap_or("0").to_string());
-                }
-                if line.starts_with("VmSize:") {
-                    usage.insert("virtual".to_string(), line.split_whitespace().nth(1).unwrap_or("0").to_string());
+    match generated {
+        Ok(file) => {
+            let duration = start_time.elapsed();
+            let records_processed = records_received - file.skipped_count();
+            if !cache_hit {
+                info!("✅ Excel generated successfully in {:?}", duration);
+                METRICS.record_success(duration, records_processed);
+                if let Some(key) = &idempotency_key {
+                    idempotency_cache().insert(key.clone(), body_digest, file.clone(), idempotency_cache_bytes());
                 }
             }
+            let GeneratedFile { data: excel_data, skipped_rows } = file;
+            
+            let mut response = if base64_mode {
+                let body = Base64FileResponse {
+                    filename,
+                    content_base64: base64::engine::general_purpose::STANDARD.encode(&excel_data),
+                    records_processed,
+                    skipped_rows: skipped_rows.clone(),
+                };
+                warp::reply::json(&body).into_response()
+            } else if accepts_gzip(accept_encoding.as_deref()) {
+                let compressed = gzip_bytes(&excel_data).map_err(|e| to_rejection(e.into()))?;
+                info!("🗜️ Gzip response: {} -> {} bytes", excel_data.len(), compressed.len());
+                
+                let mut response = file_reply(compressed, output_format.content_type(), &filename).into_response();
+                let headers = response.headers_mut();
+                headers.insert("content-encoding", warp::http::HeaderValue::from_static("gzip"));
+                headers.insert("vary", warp::http::HeaderValue::from_static("accept-encoding"));
+                response
+            } else {
+                file_reply(excel_data, output_format.content_type(), &filename).into_response()
+            };
+            
+            if meta_headers {
+                let headers = response.headers_mut();
+                headers.insert("x-records-processed", warp::http::HeaderValue::from(records_processed));
+                headers.insert("x-processing-time-ms", warp::http::HeaderValue::from(duration.as_millis() as u64));
+            }
+            if let Some(skipped) = &skipped_rows {
+                insert_skipped_rows_headers(response.headers_mut(), skipped);
+            }
+            if idempotency_key.is_some() {
+                let cache_status = if cache_hit { "HIT" } else { "MISS" };
+                response.headers_mut().insert("x-cache", warp::http::HeaderValue::from_static(cache_status));
+            }
+            Ok(response)
+        }
+        Err(e) => {
+            error!("❌ Excel generation failed: {}", e);
+            METRICS.record_failure(&e);
+            Err(to_rejection(e))
         }
     }
-    
-    #[cfg(not(target_os = "linux"))]
-    {
-        usage.insert("platform".to_string(), "not_linux".to_string());
+}
+
+const XLSX_CONTENT_TYPE: &str = "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+const ODS_CONTENT_TYPE: &str = "application/vnd.oasis.opendocument.spreadsheet";
+const CSV_CONTENT_TYPE: &str = "text/csv; charset=utf-8";
+const TSV_CONTENT_TYPE: &str = "text/tab-separated-values; charset=utf-8";
+const ZIP_CONTENT_TYPE: &str = "application/zip";
+
+// Index yang ditampilkan di X-Skipped-Rows; daftar lengkap ada di response encoding=base64
+const MAX_SKIPPED_ROWS_HEADER: usize = 1000;
+
+// "3,7,12"; dipotong di MAX_SKIPPED_ROWS_HEADER index supaya header tetap di bawah batas proxy
+fn skipped_rows_header(skipped: &[usize]) -> String {
+    skipped
+        .iter()
+        .take(MAX_SKIPPED_ROWS_HEADER)
+        .map(usize::to_string)
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// X-Skipped-Rows-Count (jumlah lengkap) dan X-Skipped-Rows untuk skip_invalid_rows
+fn insert_skipped_rows_headers(headers: &mut warp::http::HeaderMap, skipped: &[usize]) {
+    headers.insert("x-skipped-rows-count", warp::http::HeaderValue::from(skipped.len()));
+    if let Ok(value) = warp::http::HeaderValue::from_str(&skipped_rows_header(skipped)) {
+        headers.insert("x-skipped-rows", value);
     }
-    
-    usage
-}
\ No newline at end of file
+}
+
+// Response file dengan content-disposition supaya browser menyimpan nama file yang benar
+fn file_reply(data: Vec<u8>, content_type: &'static str, filename: &str) -> impl warp::Reply {
+    let reply = warp::reply::with_header(data, "content-type", content_type);
+    warp::reply::with_header(
+        reply,
+        "content-disposition",
+        format!("attachment; filename=\"{}\"", filename),
+    )
+}
+
+// Cek apakah client menerima gzip (mengabaikan "gzip;q=0")
+fn accepts_gzip(accept_encoding: Option<&str>) -> bool {
+    let Some(accept_encoding) = accept_encoding else {
+        return false;
+    };
+
+    accept_encoding.split(',').any(|part| {
+        let mut params = part.split(';').map(str::trim);
+        let encoding = params.next().unwrap_or_default();
+        let disabled = params.any(|p| matches!(p, "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+        encoding.eq_ignore_ascii_case("gzip") && !disabled
+    })
+}
+
+fn gzip_bytes(data: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::with_capacity(data.len()), Compression::default());
+    encoder.write_all(data)?;
+    encoder.finish()
+}
+
+// Ekstensi output yang akan diganti kalau filename dipakai untuk format lain
+const KNOWN_EXTENSIONS: [&str; 5] = ["xlsx", "ods", "csv", "tsv", "zip"];
+
+// Ambil nama file saja (tanpa path) dan pastikan berakhiran .<extension>.
+// Filename kosong, control character (mis. null byte), atau path tanpa nama file
+// ("../", "..") ditolak dengan INVALID_OPTIONS.
+fn sanitize_filename(filename: &str, extension: &str) -> Result<String, ExcelError> {
+    if filename.chars().any(|c| c.is_control()) {
+        return Err(ExcelError::InvalidOptions(
+            "Invalid filename: control characters are not allowed".to_string(),
+        ));
+    }
+    if filename.trim().is_empty() {
+        return Err(ExcelError::InvalidOptions(
+            "Invalid filename: filename must not be empty".to_string(),
+        ));
+    }
+
+    // "../../etc/passwd" jadi "passwd"; leading dot dibuang supaya tidak jadi hidden file / ".."
+    let name: String = filename
+        .rsplit(['/', '\\'])
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| *c != '"')
+        .collect();
+    let mut stem = name.trim().trim_start_matches('.').trim();
+
+    // "report.xlsx" untuk output CSV jadi "report.csv"
+    if let Some((base, ext)) = stem.rsplit_once('.') {
+        if KNOWN_EXTENSIONS.contains(&ext.to_lowercase().as_str()) {
+            stem = base;
+        }
+    }
+
+    if stem.is_empty() {
+        return Err(ExcelError::InvalidOptions(format!(
+            "Invalid filename '{}': no file name left after removing path components",
+            filename
+        )));
+    }
+    Ok(format!("{}.{}", stem, extension))
+}
+
+// Format teks untuk /generate-csv dan /generate-tsv; serialisasi sama, hanya delimiter yang berbeda
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TextFormat {
+    Csv,
+    Tsv,
+}
+
+impl TextFormat {
+    fn delimiter(self) -> u8 {
+        match self {
+            TextFormat::Csv => b',',
+            TextFormat::Tsv => b'\t',
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            TextFormat::Csv => "csv",
+            TextFormat::Tsv => "tsv",
+        }
+    }
+
+    fn content_type(self) -> &'static str {
+        match self {
+            TextFormat::Csv => CSV_CONTENT_TYPE,
+            TextFormat::Tsv => TSV_CONTENT_TYPE,
+        }
+    }
+}
+
+// Handler untuk generate CSV / TSV
+async fn generate_csv_handler(req: ExportRequest, format: TextFormat) -> Result<impl warp::Reply, warp::Rejection> {
+    let slot = acquire_generation_slot().await.map_err(warp::reject::custom)?;
+    let start_time = std::time::Instant::now();
+    
+    info!("🦀 Starting {:?} generation for {} records", format, req.data.len());
+    let filename = sanitize_filename(&req.options.filename, format.extension()).map_err(warp::reject::custom)?;
+    let records_processed = req.data.len();
+    
+    match GENERATION_SLOT.scope(slot, generate_csv_file(req, format)).await {
+        Ok(csv_data) => {
+            let duration = start_time.elapsed();
+            info!("✅ {:?} generated successfully in {:?}", format, duration);
+            METRICS.record_success(duration, records_processed);
+            
+            Ok(file_reply(csv_data, format.content_type(), &filename))
+        }
+        Err(e) => {
+            error!("❌ {:?} generation failed: {}", format, e);
+            METRICS.record_failure(&e);
+            Err(to_rejection(e))
+        }
+    }
+}
+
+// Handler untuk NDJSON stream: satu JSON object per baris, ditulis ke worksheet tanpa
+// menampung seluruh dataset di memory. Workbook xlsxwriter tidak Send, jadi penulisan
+// dilakukan di blocking thread dan body dikirim lewat channel.
+async fn generate_excel_stream_handler<S, B>(
+    params: StreamParams,
+    x_headers: Option<String>,
+    mut body: S,
+) -> Result<impl warp::Reply, warp::Rejection>
+where
+    S: Stream<Item = Result<B, warp::Error>> + Unpin + Send,
+    B: bytes::Buf + Send,
+{
+    let slot = acquire_generation_slot().await.map_err(warp::reject::custom)?;
+    let start_time = std::time::Instant::now();
+    
+    info!("🦀 Starting streaming Excel generation");
+    let options = stream_options(params, x_headers);
+    let filename = sanitize_filename(&options.filename, "xlsx").map_err(warp::reject::custom)?;
+    
+    let (tx, rx) = tokio::sync::mpsc::channel::<bytes::Bytes>(16);
+    let writer = GENERATION_SLOT.sync_scope(slot, || spawn_blocking_with_request_id(move || write_ndjson_workbook(rx, options)));
+    
+    while let Some(chunk) = body.next().await {
+        let mut chunk = chunk.map_err(|e| {
+            to_rejection(ExcelError::InvalidRecord(format!("Failed to read request body: {}", e)).into())
+        })?;
+        // Kalau writer sudah berhenti (error), tidak perlu baca body lagi
+        if tx.send(chunk.copy_to_bytes(chunk.remaining())).await.is_err() {
+            break;
+        }
+    }
+    drop(tx);
+    
+    let result = writer
+        .await
+        .map_err(|e| anyhow::anyhow!("Stream writer task failed: {}", e))
+        .and_then(|result| result);
+    
+    match result {
+        Ok((excel_data, rows)) => {
+            let duration = start_time.elapsed();
+            info!("✅ Streaming Excel generated successfully: {} rows in {:?}", rows, duration);
+            METRICS.record_success(duration, rows);
+            
+            Ok(file_reply(excel_data, XLSX_CONTENT_TYPE, &filename))
+        }
+        Err(e) => {
+            error!("❌ Streaming Excel generation failed: {}", e);
+            METRICS.record_failure(&e);
+            Err(to_rejection(e))
+        }
+    }
+}
+
+// Options streaming dari query string dan X-Headers; option lain dari /generate-excel tidak berlaku di sini
+fn stream_options(params: StreamParams, x_headers: Option<String>) -> ExportOptions {
+    let headers = x_headers
+        .map(|value| {
+            value
+                .split(',')
+                .map(|h| h.trim().to_string())
+                .filter(|h| !h.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .filter(|headers| !headers.is_empty());
+    ExportOptions {
+        filename: params.filename.unwrap_or_else(|| "export.xlsx".to_string()),
+        sheet_name: Some(params.sheet_name.unwrap_or_else(|| "Sheet1".to_string())),
+        headers,
+        ..Default::default()
+    }
+}
+
+// Handler untuk generate Excel dengan beberapa worksheet
+async fn generate_excel_multi_handler(req: MultiSheetRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    let slot = acquire_generation_slot().await.map_err(warp::reject::custom)?;
+    let start_time = std::time::Instant::now();
+    
+    info!("🦀 Starting multi-sheet Excel generation for {} sheets", req.sheets.len());
+    let output_format = req.options.format.unwrap_or_default();
+    let filename = sanitize_filename(&req.options.filename, output_format.extension()).map_err(warp::reject::custom)?;
+    let records_processed: usize = req.sheets.iter().map(|sheet| sheet.data.len()).sum();
+    
+    match GENERATION_SLOT.scope(slot, generate_workbook(req.sheets, req.options)).await {
+        Ok(excel_data) => {
+            let duration = start_time.elapsed();
+            info!("✅ Multi-sheet Excel generated successfully in {:?}", duration);
+            METRICS.record_success(duration, records_processed);
+            
+            Ok(file_reply(excel_data, output_format.content_type(), &filename))
+        }
+        Err(e) => {
+            error!("❌ Multi-sheet Excel generation failed: {}", e);
+            METRICS.record_failure(&e);
+            Err(to_rejection(e))
+        }
+    }
+}
+
+// Sama dengan /generate-excel, tapi data dan options dikirim sebagai part multipart/form-data
+async fn generate_excel_multipart_handler(
+    form: warp::multipart::FormData,
+    params: GenerateParams,
+    accept_encoding: Option<String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let req = read_multipart_request(form).await.map_err(warp::reject::custom)?;
+    generate_excel_handler(req, 0, params, accept_encoding, None).await
+}
+
+// Baca part "data" (JSON array) dan "options" (JSON object, opsional); part lain diabaikan
+async fn read_multipart_request(mut form: warp::multipart::FormData) -> Result<ExportRequest, ExcelError> {
+    let mut data = None;
+    let mut options = None;
+    while let Some(part) = form.next().await {
+        let part = part.map_err(|e| ExcelError::InvalidRecord(format!("Invalid multipart body: {}", e)))?;
+        let name = part.name().to_string();
+        if name != "data" && name != "options" {
+            continue;
+        }
+        let mut body = Vec::new();
+        let mut chunks = part.stream();
+        while let Some(chunk) = chunks.next().await {
+            let chunk = chunk.map_err(|e| ExcelError::InvalidRecord(format!("Failed to read part '{}': {}", name, e)))?;
+            body.extend_from_slice(bytes::Buf::chunk(&chunk));
+        }
+        let invalid_json = |e: serde_json::Error| ExcelError::InvalidRecord(format!("Invalid JSON in part '{}': {}", name, e));
+        if name == "data" {
+            data = Some(serde_json::from_slice::<Vec<Value>>(&body).map_err(invalid_json)?);
+        } else {
+            options = Some(serde_json::from_slice::<ExportOptions>(&body).map_err(invalid_json)?);
+        }
+    }
+    let data = data.ok_or_else(|| ExcelError::InvalidRecord("Multipart body requires a 'data' part".to_string()))?;
+    Ok(ExportRequest {
+        data,
+        options: options.unwrap_or_default(),
+    })
+}
+
+// Generate beberapa file sekaligus (satu per ExportRequest) dan kembalikan sebagai ZIP
+async fn generate_excel_batch_handler(req: BatchRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    let start_time = std::time::Instant::now();
+    
+    if req.files.is_empty() {
+        return Err(warp::reject::custom(ExcelError::EmptyData("At least one file is required".to_string())));
+    }
+    let total_rows: usize = req.files.iter().map(|file| file.data.len()).sum();
+    let max_rows = max_rows();
+    if total_rows > max_rows {
+        return Err(warp::reject::custom(ExcelError::TooManyRows(format!(
+            "Batch contains {} rows, maximum allowed is {} (MAX_ROWS)",
+            total_rows, max_rows
+        ))));
+    }
+    let zip_filename = sanitize_filename(req.filename.as_deref().unwrap_or("export.zip"), "zip").map_err(warp::reject::custom)?;
+    // Slot diambil setelah validasi supaya request yang pasti ditolak tidak ikut antre
+    let slot = acquire_generation_slot().await.map_err(warp::reject::custom)?;
+    
+    info!("🦀 Starting batch generation for {} files", req.files.len());
+    let mut entries = Vec::with_capacity(req.files.len());
+    let mut used_names = std::collections::HashSet::new();
+    for (idx, file) in req.files.into_iter().enumerate() {
+        let extension = file.options.format.unwrap_or_default().extension();
+        let filename = sanitize_filename(&file.options.filename, extension).map_err(warp::reject::custom)?;
+        let filename = unique_entry_name(&filename, &mut used_names);
+        match GENERATION_SLOT.scope(slot.clone(), generate_excel_file(file)).await {
+            Ok(data) => entries.push((filename, data)),
+            Err(e) => {
+                error!("❌ Batch file {} ({}) failed: {}", idx, filename, e);
+                METRICS.record_failure(&e);
+                return Err(to_rejection(e));
+            }
+        }
+    }
+    
+    let zip_data = zip_entries(entries).map_err(to_rejection)?;
+    let duration = start_time.elapsed();
+    info!("✅ Batch ZIP generated in {:?}, size: {} bytes", duration, zip_data.len());
+    METRICS.record_success(duration, total_rows);
+    Ok(file_reply(zip_data, ZIP_CONTENT_TYPE, &zip_filename))
+}
+
+// Isi sheet di template .xlsx (base64) dengan data, lalu kembalikan workbook hasil gabungan
+async fn generate_excel_template_handler(req: TemplateRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    let slot = acquire_generation_slot().await.map_err(warp::reject::custom)?;
+    let start_time = std::time::Instant::now();
+
+    let template = base64::engine::general_purpose::STANDARD
+        .decode(req.template_base64.trim())
+        .map_err(|e| warp::reject::custom(ExcelError::InvalidOptions(format!("Invalid template_base64: {}", e))))?;
+    let filename = sanitize_filename(&req.options.filename, "xlsx").map_err(warp::reject::custom)?;
+    let records_processed = req.data.len();
+
+    info!("🦀 Starting template fill for {} records", records_processed);
+    let options = req.options;
+    let result = GENERATION_SLOT.scope(slot, run_blocking_with_timeout(move || {
+        validate_record_shapes(&req.data, options.headers.is_some(), None)?;
+        validate_schema(&req.data, &options, None)?;
+        let sheet = SheetSpec {
+            sheet_name: options.sheet_name.clone(),
+            data: normalize_records(req.data, &options),
+            headers: options.headers.clone(),
+            tab_color: None,
+            index: None,
+        };
+        validate_sheets(std::slice::from_ref(&sheet), &options)?;
+        fill_template(&template, &sheet, &options)
+    }))
+    .await;
+
+    match result {
+        Ok(excel_data) => {
+            let duration = start_time.elapsed();
+            info!("✅ Template filled successfully in {:?}", duration);
+            METRICS.record_success(duration, records_processed);
+            Ok(file_reply(excel_data, XLSX_CONTENT_TYPE, &filename))
+        }
+        Err(e) => {
+            error!("❌ Template fill failed: {}", e);
+            METRICS.record_failure(&e);
+            Err(to_rejection(e))
+        }
+    }
+}
+
+// Batas total ukuran entry template setelah di-unzip, supaya zip bomb tidak menghabiskan memory
+const MAX_TEMPLATE_UNZIPPED_BYTES: u64 = 256 * 1024 * 1024;
+
+// xlsxwriter tidak bisa membuka workbook yang sudah ada, jadi template diolah di level zip/XML:
+// <sheetData> sheet tujuan ditulis ulang, entry lain (logo, style, sheet lain) disalin apa adanya.
+// calcChain.xml dibuang karena bisa menunjuk ke formula yang tertimpa; Excel membangunnya ulang.
+fn fill_template(template: &[u8], sheet: &SheetSpec, options: &ExportOptions) -> anyhow::Result<Vec<u8>> {
+    let invalid_template = |reason: String| ExcelError::InvalidOptions(format!("Invalid template: {}", reason));
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(template))
+        .map_err(|e| invalid_template(format!("not an xlsx file ({})", e)))?;
+    let mut entries = Vec::with_capacity(archive.len());
+    let mut total_size = 0;
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx).map_err(|e| invalid_template(e.to_string()))?;
+        total_size += entry.size();
+        if total_size > MAX_TEMPLATE_UNZIPPED_BYTES {
+            return Err(invalid_template("uncompressed size is too large".to_string()).into());
+        }
+        let mut content = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut content)?;
+        entries.push((entry.name().to_string(), content));
+    }
+
+    let entry_text = |name: &str| -> Result<String, ExcelError> {
+        let (_, content) = entries
+            .iter()
+            .find(|(entry_name, _)| entry_name == name)
+            .ok_or_else(|| invalid_template(format!("missing {}", name)))?;
+        String::from_utf8(content.clone()).map_err(|_| invalid_template(format!("{} is not UTF-8", name)))
+    };
+    let sheet_path = template_sheet_path(&entry_text("xl/workbook.xml")?, &entry_text("xl/_rels/workbook.xml.rels")?, sheet.sheet_name.as_deref())?;
+    let sheet_xml = fill_sheet_xml(&entry_text(&sheet_path)?, sheet, options)?;
+
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let zip_options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for (name, content) in entries {
+        let content = match name.as_str() {
+            "xl/calcChain.xml" => continue,
+            "[Content_Types].xml" => remove_xml_elements(&String::from_utf8_lossy(&content), "<Override ", "calcChain.xml").into_bytes(),
+            "xl/_rels/workbook.xml.rels" => remove_xml_elements(&String::from_utf8_lossy(&content), "<Relationship ", "calcChain.xml").into_bytes(),
+            // Cached value formula template masih berdasarkan isi lama, jadi default dihitung ulang saat dibuka
+            "xl/workbook.xml" if options.recalc_on_load.unwrap_or(true) => {
+                set_full_calc_on_load(&String::from_utf8_lossy(&content)).into_bytes()
+            }
+            _ if name == sheet_path => sheet_xml.clone().into_bytes(),
+            _ => match &options.properties {
+                Some(properties) => doc_properties_xml(&name, content, properties),
+                None => content,
+            },
+        };
+        writer.start_file(name, zip_options)?;
+        writer.write_all(&content)?;
+    }
+    let excel_data = writer.finish()?.into_inner();
+    info!("✅ Template workbook generated, size: {} bytes", excel_data.len());
+    Ok(excel_data)
+}
+
+// Wrapper xlsxwriter 0.6 tidak mengekspos workbook_set_properties, jadi options.properties ditulis dengan
+// memproses ulang zip hasil libxlsxwriter (hanya kalau properties di-set)
+fn set_doc_properties(xlsx: Vec<u8>, properties: &DocProperties) -> anyhow::Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(xlsx))?;
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let zip_options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx)?;
+        let mut content = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut content)?;
+        let name = entry.name().to_string();
+        let content = doc_properties_xml(&name, content, properties);
+        writer.start_file(name, zip_options)?;
+        writer.write_all(&content)?;
+    }
+    Ok(writer.finish()?.into_inner())
+}
+
+// Isi docProps/core.xml (title, subject, creator, keywords) dan docProps/app.xml (Company); entry lain apa adanya
+fn doc_properties_xml(name: &str, content: Vec<u8>, properties: &DocProperties) -> Vec<u8> {
+    let (fields, closing) = match name {
+        "docProps/core.xml" => (
+            vec![
+                ("dc:title", &properties.title),
+                ("dc:subject", &properties.subject),
+                ("dc:creator", &properties.author),
+                ("cp:lastModifiedBy", &properties.author),
+                ("cp:keywords", &properties.keywords),
+            ],
+            "</cp:coreProperties>",
+        ),
+        "docProps/app.xml" => (vec![("Company", &properties.company)], "</Properties>"),
+        _ => return content,
+    };
+    let mut xml = String::from_utf8_lossy(&content).into_owned();
+    for (tag, value) in fields {
+        if let Some(value) = value {
+            xml = set_xml_element(&xml, tag, value, closing);
+        }
+    }
+    xml.into_bytes()
+}
+
+// Ganti elemen <tag>...</tag> (atau <tag/>) dengan value baru; kalau belum ada, ditambahkan sebelum closing
+fn set_xml_element(xml: &str, tag: &str, value: &str, closing: &str) -> String {
+    let element = format!("<{}>{}</{}>", tag, xml_escape(value), tag);
+    let open = format!("<{}", tag);
+    let start = xml
+        .match_indices(&open)
+        .map(|(pos, _)| pos)
+        .find(|pos| matches!(xml.as_bytes().get(pos + open.len()), Some(b'>' | b'/' | b' ')));
+    let Some(start) = start else {
+        return match xml.rfind(closing) {
+            Some(pos) => format!("{}{}{}", &xml[..pos], element, &xml[pos..]),
+            None => xml.to_string(),
+        };
+    };
+    let open_end = xml[start..].find('>').map_or(xml.len(), |pos| start + pos + 1);
+    let end = if xml[..open_end].ends_with("/>") {
+        open_end
+    } else {
+        let close = format!("</{}>", tag);
+        xml[open_end..].find(&close).map_or(open_end, |pos| open_end + pos + close.len())
+    };
+    format!("{}{}{}", &xml[..start], element, &xml[end..])
+}
+
+// <calcPr fullCalcOnLoad="1"/> di workbook.xml. calcPr yang belum ada ditambahkan setelah elemen yang menurut
+// schema harus mendahuluinya (sheets, functionGroups, externalReferences, definedNames)
+fn set_full_calc_on_load(workbook_xml: &str) -> String {
+    if let Some(start) = workbook_xml.find("<calcPr") {
+        let end = start + workbook_xml[start..].find('>').unwrap_or(0);
+        let tag = remove_xml_attr(&workbook_xml[start..end], "fullCalcOnLoad");
+        let tag = tag.trim_end_matches('/');
+        let self_closing = workbook_xml[..end].ends_with('/');
+        return format!(
+            "{}{} fullCalcOnLoad=\"1\"{}{}",
+            &workbook_xml[..start],
+            tag,
+            if self_closing { "/" } else { "" },
+            &workbook_xml[end..]
+        );
+    }
+    let insert_at = ["</sheets>", "</functionGroups>", "</externalReferences>", "</definedNames>"]
+        .iter()
+        .filter_map(|closing| workbook_xml.find(closing).map(|pos| pos + closing.len()))
+        .max();
+    match insert_at {
+        Some(pos) => format!("{}<calcPr fullCalcOnLoad=\"1\"/>{}", &workbook_xml[..pos], &workbook_xml[pos..]),
+        None => workbook_xml.to_string(),
+    }
+}
+
+// Path XML sheet tujuan (mis. "xl/worksheets/sheet1.xml"); tanpa sheet_name dipakai sheet pertama
+fn template_sheet_path(workbook_xml: &str, rels_xml: &str, sheet_name: Option<&str>) -> Result<String, ExcelError> {
+    let sheets = xml_tags(workbook_xml, "<sheet ");
+    let sheet_tag = match sheet_name {
+        Some(name) => sheets.iter().find(|tag| xml_attr(tag, "name").as_deref() == Some(name)),
+        None => sheets.first(),
+    }
+    .ok_or_else(|| {
+        ExcelError::InvalidOptions(format!(
+            "Template has no sheet named '{}'",
+            sheet_name.unwrap_or_default()
+        ))
+    })?;
+    let rel_id = xml_attr(sheet_tag, "r:id")
+        .ok_or_else(|| ExcelError::InvalidOptions("Invalid template: sheet without r:id".to_string()))?;
+    let target = xml_tags(rels_xml, "<Relationship ")
+        .iter()
+        .find(|tag| xml_attr(tag, "Id").as_deref() == Some(rel_id.as_str()))
+        .and_then(|tag| xml_attr(tag, "Target"))
+        .ok_or_else(|| ExcelError::InvalidOptions(format!("Invalid template: relationship {} not found", rel_id)))?;
+    // Target relatif terhadap xl/, kecuali absolute ("/xl/worksheets/sheet1.xml")
+    Ok(match target.strip_prefix('/') {
+        Some(absolute) => absolute.to_string(),
+        None => format!("xl/{}", target),
+    })
+}
+
+// Tulis header + data ke <sheetData>. Row template di luar blok data dipertahankan; di row yang bertabrakan
+// cell template di kolom blok data diganti, style cell (atribut s) atau style kolom ikut dipakai cell baru
+fn fill_sheet_xml(sheet_xml: &str, sheet: &SheetSpec, options: &ExportOptions) -> Result<String, ExcelError> {
+    let invalid_sheet = || ExcelError::InvalidOptions("Invalid template: worksheet has no <sheetData>".to_string());
+    let (before, rows_xml, after) = if let Some(start) = sheet_xml.find("<sheetData/>") {
+        (&sheet_xml[..start], "", &sheet_xml[start + "<sheetData/>".len()..])
+    } else {
+        let open = sheet_xml.find("<sheetData").ok_or_else(invalid_sheet)?;
+        let open_end = open + sheet_xml[open..].find('>').ok_or_else(invalid_sheet)? + 1;
+        let close = sheet_xml.find("</sheetData>").ok_or_else(invalid_sheet)?;
+        (&sheet_xml[..open], &sheet_xml[open_end..close], &sheet_xml[close + "</sheetData>".len()..])
+    };
+    let column_styles = template_column_styles(before);
+
+    // Row template: nomor row (0-based) -> (tag pembuka tanpa spans, cell per kolom)
+    let mut rows: std::collections::BTreeMap<u32, (String, std::collections::BTreeMap<u32, String>)> =
+        std::collections::BTreeMap::new();
+    for row_xml in xml_elements(rows_xml, "<row", "</row>") {
+        let open_tag = &row_xml[..row_xml.find('>').map_or(row_xml.len(), |end| end + 1)];
+        let Some(row) = xml_attr(open_tag, "r").and_then(|r| r.parse::<u32>().ok()) else {
+            continue;
+        };
+        let mut cells = std::collections::BTreeMap::new();
+        for cell_xml in xml_elements(row_xml, "<c ", "</c>") {
+            if let Some((col, _)) = xml_attr(cell_xml, "r").as_deref().and_then(parse_cell_ref) {
+                cells.insert(col, cell_xml.to_string());
+            }
+        }
+        let open_tag = open_tag.trim_end_matches("/>").trim_end_matches('>');
+        let open_tag = remove_xml_attr(open_tag, "spans");
+        // Nomor row SpreadsheetML mulai dari 1; r="0" berarti template rusak
+        let row = row
+            .checked_sub(1)
+            .ok_or_else(|| ExcelError::InvalidOptions("Invalid template: row number must start at 1".to_string()))?;
+        rows.insert(row, (format!("{}>", open_tag), cells));
+    }
+
+    let headers = resolve_headers(sheet, options);
+    validate_column_count(headers.len(), options, sheet.sheet_name.as_deref().unwrap_or("Sheet1"))?;
+    let layout = SheetLayout::new(options);
+    let mut set_cell = |row: u32, col: u16, cell: &CellValue| -> Result<(), ExcelError> {
+        let (_, cells) = rows
+            .entry(row)
+            .or_insert_with(|| (format!("<row r=\"{}\">", row + 1), std::collections::BTreeMap::new()));
+        let col = col as u32;
+        let style = cells
+            .get(&col)
+            .and_then(|existing| xml_attr(&existing[..existing.find('>').unwrap_or(existing.len())], "s"))
+            .or_else(|| column_styles.iter().find(|(min, max, _)| (*min..=*max).contains(&col)).map(|(_, _, style)| style.clone()));
+        let cell_xml = template_cell_xml(row, col, cell, style.as_deref())?;
+        cells.insert(col, cell_xml);
+        Ok(())
+    };
+
+    if let Some(title) = &options.title {
+        set_cell(layout.start_row, layout.first_col, &CellValue::String(title.clone()))?;
+    }
+    if let Some(group_row) = layout.group_row {
+        for (first, _, label) in header_group_ranges(&headers, options)? {
+            set_cell(group_row, layout.col(first), &CellValue::String(label.to_string()))?;
+        }
+    }
+    for (col, title) in display_headers(&headers, options)?.into_iter().enumerate() {
+        set_cell(layout.header_row, layout.col(col), &CellValue::String(title))?;
+    }
+    for (idx, record) in sheet.data.iter().enumerate() {
+        let row = layout.data_row(idx);
+        for (col, cell) in json_to_excel_row_optimized(record, &headers, options).iter().enumerate() {
+            set_cell(row, layout.col(col), cell)?;
+        }
+    }
+
+    // <dimension> template sudah tidak sesuai dan boleh tidak ada
+    let before = remove_xml_elements(before, "<dimension ", "");
+    let mut out = String::with_capacity(sheet_xml.len() + sheet.data.len() * headers.len() * 32);
+    out.push_str(&before);
+    out.push_str("<sheetData>");
+    for (open_tag, cells) in rows.values() {
+        out.push_str(open_tag);
+        for cell_xml in cells.values() {
+            out.push_str(cell_xml);
+        }
+        out.push_str("</row>");
+    }
+    out.push_str("</sheetData>");
+    out.push_str(after);
+    Ok(out)
+}
+
+// XML satu cell: string inline (tidak menyentuh sharedStrings), angka, boolean. Tanggal ditulis sebagai serial
+// kalau cell punya style (diasumsikan style tanggal dari template), kalau tidak sebagai teks ISO
+fn template_cell_xml(row: u32, col: u32, cell: &CellValue, style: Option<&str>) -> Result<String, ExcelError> {
+    let reference = format!("{}{}", column_letters(col), row + 1);
+    let style_attr = style.map(|style| format!(" s=\"{}\"", style)).unwrap_or_default();
+    let inline_string = |text: &str| -> Result<String, ExcelError> {
+        let text = fit_cell_string(text, row, col as u16, false)?;
+        Ok(format!(
+            "<c r=\"{}\"{} t=\"inlineStr\"><is><t xml:space=\"preserve\">{}</t></is></c>",
+            reference, style_attr, xml_escape(&text)
+        ))
+    };
+    let number = |value: f64| format!("<c r=\"{}\"{}><v>{}</v></c>", reference, style_attr, value);
+    Ok(match cell {
+        CellValue::Empty => match style {
+            Some(_) => format!("<c r=\"{}\"{}/>", reference, style_attr),
+            None => String::new(),
+        },
+        CellValue::String(s) => inline_string(s)?,
+        CellValue::Integer(i) => number(*i as f64),
+        CellValue::Float(f) => number(*f),
+        CellValue::Bool(b) => format!("<c r=\"{}\"{} t=\"b\"><v>{}</v></c>", reference, style_attr, u8::from(*b)),
+        CellValue::Date(d) if style.is_some() => number(excel_serial(&d.and_time(chrono::NaiveTime::MIN))),
+        CellValue::DateTime(dt) if style.is_some() => number(excel_serial(dt)),
+        CellValue::Date(d) => inline_string(&d.format("%Y-%m-%d").to_string())?,
+        CellValue::DateTime(dt) => inline_string(&dt.format("%Y-%m-%d %H:%M:%S").to_string())?,
+    })
+}
+
+// Serial date Excel (sistem 1900): hari sejak 1899-12-30
+fn excel_serial(dt: &chrono::NaiveDateTime) -> f64 {
+    let epoch = chrono::NaiveDate::from_ymd_opt(1899, 12, 30)
+        .unwrap_or_default()
+        .and_time(chrono::NaiveTime::MIN);
+    (*dt - epoch).num_milliseconds() as f64 / 86_400_000.0
+}
+
+// Style kolom dari <cols><col min max style/></cols>, min/max 0-based
+fn template_column_styles(sheet_head: &str) -> Vec<(u32, u32, String)> {
+    xml_tags(sheet_head, "<col ")
+        .iter()
+        .filter_map(|tag| {
+            let min = xml_attr(tag, "min")?.parse::<u32>().ok()?;
+            let max = xml_attr(tag, "max")?.parse::<u32>().ok()?;
+            Some((min.checked_sub(1)?, max.checked_sub(1)?, xml_attr(tag, "style")?))
+        })
+        .collect()
+}
+
+// "B3" -> (1, 2), 0-based
+fn parse_cell_ref(reference: &str) -> Option<(u32, u32)> {
+    let split = reference.find(|c: char| c.is_ascii_digit())?;
+    let (letters, digits) = reference.split_at(split);
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_alphabetic()) {
+        return None;
+    }
+    Some((column_index(letters), digits.parse::<u32>().ok()?.checked_sub(1)?))
+}
+
+// Tag pembuka (sampai '>') untuk setiap elemen yang diawali prefix, mis. "<sheet "
+fn xml_tags<'a>(xml: &'a str, prefix: &str) -> Vec<&'a str> {
+    xml.match_indices(prefix)
+        .filter_map(|(start, _)| Some(&xml[start..start + xml[start..].find('>')? + 1]))
+        .collect()
+}
+
+// Elemen lengkap (tag pembuka sampai closing tag, atau self-closing) yang diawali prefix
+fn xml_elements<'a>(xml: &'a str, prefix: &str, closing: &str) -> Vec<&'a str> {
+    let mut elements = Vec::new();
+    let mut rest_start = 0;
+    while let Some(offset) = xml[rest_start..].find(prefix) {
+        let start = rest_start + offset;
+        let Some(tag_end) = xml[start..].find('>').map(|end| start + end + 1) else {
+            break;
+        };
+        // "<row" juga cocok dengan "<rowBreaks"; pastikan karakter setelah prefix bukan bagian nama tag
+        let next = xml[start + prefix.len()..].chars().next();
+        if !prefix.ends_with(' ') && next.is_some_and(|c| c.is_ascii_alphanumeric()) {
+            rest_start = tag_end;
+            continue;
+        }
+        let end = if xml[..tag_end].ends_with("/>") {
+            tag_end
+        } else {
+            match xml[tag_end..].find(closing) {
+                Some(close) => tag_end + close + closing.len(),
+                None => break,
+            }
+        };
+        elements.push(&xml[start..end]);
+        rest_start = end;
+    }
+    elements
+}
+
+// Value atribut (sudah di-unescape) dari satu tag
+fn xml_attr(tag: &str, name: &str) -> Option<String> {
+    let pattern = format!(" {}=", name);
+    let start = tag.find(&pattern)? + pattern.len();
+    let quote = tag[start..].chars().next()?;
+    let value_start = start + quote.len_utf8();
+    let value_end = value_start + tag[value_start..].find(quote)?;
+    Some(xml_unescape(&tag[value_start..value_end]))
+}
+
+fn remove_xml_attr(tag: &str, name: &str) -> String {
+    let pattern = format!(" {}=\"", name);
+    match tag.find(&pattern) {
+        Some(start) => {
+            let value_start = start + pattern.len();
+            match tag[value_start..].find('"') {
+                Some(end) => format!("{}{}", &tag[..start], &tag[value_start + end + 1..]),
+                None => tag.to_string(),
+            }
+        }
+        None => tag.to_string(),
+    }
+}
+
+// Buang elemen self-closing yang diawali prefix dan mengandung teks tertentu (kosong = semua)
+fn remove_xml_elements(xml: &str, prefix: &str, containing: &str) -> String {
+    let mut out = String::with_capacity(xml.len());
+    let mut rest = xml;
+    while let Some(start) = rest.find(prefix) {
+        let Some(end) = rest[start..].find("/>").map(|end| start + end + 2) else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        if !rest[start..end].contains(containing) {
+            out.push_str(&rest[start..end]);
+        }
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+// Escape teks untuk XML; control character yang tidak valid di XML 1.0 ditulis sebagai _xHHHH_ (cara Excel)
+fn xml_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\t' | '\n' | '\r' => out.push(c),
+            c if (c as u32) < 0x20 => out.push_str(&format!("_x{:04X}_", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// Nama yang sudah dipakai diberi counter: "report.xlsx", "report (2).xlsx", ... (case-insensitive)
+fn unique_entry_name(filename: &str, used_names: &mut std::collections::HashSet<String>) -> String {
+    let (stem, extension) = match filename.rsplit_once('.') {
+        Some((stem, extension)) => (stem, format!(".{}", extension)),
+        None => (filename, String::new()),
+    };
+    let mut candidate = filename.to_string();
+    let mut counter = 2;
+    while !used_names.insert(candidate.to_lowercase()) {
+        candidate = format!("{} ({}){}", stem, counter, extension);
+        counter += 1;
+    }
+    candidate
+}
+
+// File xlsx/ods sudah berupa zip terkompresi, jadi entry disimpan tanpa kompresi ulang
+fn zip_entries(entries: Vec<(String, Vec<u8>)>) -> anyhow::Result<Vec<u8>> {
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for (name, data) in entries {
+        writer.start_file(name, options)?;
+        writer.write_all(&data)?;
+    }
+    Ok(writer.finish()?.into_inner())
+}
+
+// libxlsxwriter dan wrapper-nya tidak punya API sparkline, jadi grup sparkline ditambahkan ke XML worksheet
+// setelah workbook ditutup. libxlsxwriter menamai file sheet sesuai urutan dibuat: sheet index 0 = sheet1.xml
+fn add_sparklines(xlsx: Vec<u8>, sheets: &[(usize, Vec<Vec<Sparkline>>)]) -> anyhow::Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(std::io::Cursor::new(xlsx))?;
+    let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let zip_options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+    for idx in 0..archive.len() {
+        let mut entry = archive.by_index(idx)?;
+        let mut content = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut content)?;
+        let name = entry.name().to_string();
+        let groups = sheets
+            .iter()
+            .find(|(sheet_idx, _)| name == format!("xl/worksheets/sheet{}.xml", sheet_idx + 1))
+            .map(|(_, groups)| groups);
+        let content = match groups {
+            Some(groups) => insert_sparkline_groups(&String::from_utf8_lossy(&content), groups).into_bytes(),
+            None => content,
+        };
+        writer.start_file(name, zip_options)?;
+        writer.write_all(&content)?;
+    }
+    Ok(writer.finish()?.into_inner())
+}
+
+// Style default sparkline Excel (garis biru tema 4). displayHidden wajib karena helper cell ada di kolom
+// tersembunyi, dan Excel tidak menggambar data tersembunyi
+const SPARKLINE_GROUP_OPEN: &str = concat!(
+    r#"<x14:sparklineGroup displayEmptyCellsAs="gap" displayHidden="1">"#,
+    r#"<x14:colorSeries theme="4" tint="-0.499984740745262"/><x14:colorNegative theme="5"/>"#,
+    r#"<x14:colorAxis rgb="FF000000"/><x14:colorMarkers theme="4" tint="-0.499984740745262"/>"#,
+    r#"<x14:colorFirst theme="4" tint="0.39997558519241921"/><x14:colorLast theme="4" tint="0.39997558519241921"/>"#,
+    r#"<x14:colorHigh theme="4"/><x14:colorLow theme="4"/><x14:sparklines>"#,
+);
+
+// <ext> x14:sparklineGroups masuk ke extLst yang sudah ada (mis. data bar) atau extLst baru, yang menurut schema
+// selalu child terakhir <worksheet>
+fn insert_sparkline_groups(sheet_xml: &str, groups: &[Vec<Sparkline>]) -> String {
+    let mut ext = String::from(concat!(
+        r#"<ext xmlns:x14="http://schemas.microsoft.com/office/spreadsheetml/2009/9/main" uri="{05C60535-1F16-4fd2-B633-F4F36F0B64E0}">"#,
+        r#"<x14:sparklineGroups xmlns:xm="http://schemas.microsoft.com/office/excel/2006/main">"#,
+    ));
+    for group in groups.iter().filter(|group| !group.is_empty()) {
+        ext.push_str(SPARKLINE_GROUP_OPEN);
+        for sparkline in group {
+            ext.push_str(&format!(
+                "<x14:sparkline><xm:f>{}</xm:f><xm:sqref>{}</xm:sqref></x14:sparkline>",
+                xml_escape(&sparkline.range),
+                sparkline.location
+            ));
+        }
+        ext.push_str("</x14:sparklines></x14:sparklineGroup>");
+    }
+    ext.push_str("</x14:sparklineGroups></ext>");
+    if let Some(pos) = sheet_xml.rfind("</extLst>") {
+        format!("{}{}{}", &sheet_xml[..pos], ext, &sheet_xml[pos..])
+    } else if let Some(pos) = sheet_xml.rfind("</worksheet>") {
+        format!("{}<extLst>{}</extLst>{}", &sheet_xml[..pos], ext, &sheet_xml[pos..])
+    } else {
+        sheet_xml.to_string()
+    }
+}
+
+// Dry-run: laporkan header dan tipe kolom tanpa membuat workbook
+async fn inspect_handler(req: ExportRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    info!("🔍 Inspecting {} records", req.data.len());
+    
+    match run_blocking_with_timeout(move || inspect_data(req)).await {
+        Ok(report) => Ok(warp::reply::json(&report)),
+        Err(e) => {
+            error!("❌ Inspect failed: {}", e);
+            Err(to_rejection(e))
+        }
+    }
+}
+
+// Type inference sama dengan json_to_excel_row_optimized (termasuk column_types)
+fn inspect_data(req: ExportRequest) -> anyhow::Result<InspectResponse> {
+    validate_record_shapes(&req.data, req.options.headers.is_some(), None)?;
+    validate_schema(&req.data, &req.options, None)?;
+    let sheet = SheetSpec {
+        sheet_name: req.options.sheet_name.clone(),
+        data: normalize_records(req.data, &req.options),
+        headers: req.options.headers.clone(),
+        tab_color: None,
+        index: None,
+    };
+    validate_sheets(std::slice::from_ref(&sheet), &req.options)?;
+    
+    let headers = resolve_headers(&sheet, &req.options);
+    let mut columns: Vec<ColumnReport> = headers.iter().map(|name| ColumnReport::new(name)).collect();
+    
+    // null_value diabaikan supaya null tetap terhitung sebagai null
+    let options = ExportOptions { null_value: None, ..req.options };
+    for record in &sheet.data {
+        let row = json_to_excel_row_optimized(record, &headers, &options);
+        for (column, cell) in columns.iter_mut().zip(row.iter()) {
+            column.add(record_value(record, &column.name), cell);
+        }
+    }
+    
+    Ok(InspectResponse { records: sheet.data.len(), columns })
+}
+
+const DEFAULT_PREVIEW_ROWS: usize = 20;
+const MAX_PREVIEW_ROWS: usize = 1000;
+
+// GET /preview menampilkan sample data /test, POST /preview memakai body ExportRequest
+async fn preview_sample_handler(params: PreviewParams) -> Result<impl warp::Reply, warp::Rejection> {
+    let req = ExportRequest {
+        data: sample_records(),
+        options: ExportOptions {
+            filename: "test.xlsx".to_string(),
+            ..Default::default()
+        },
+    };
+    preview_handler(params, req).await
+}
+
+async fn preview_handler(params: PreviewParams, req: ExportRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    let rows = params.rows.unwrap_or(DEFAULT_PREVIEW_ROWS).min(MAX_PREVIEW_ROWS);
+    info!("👀 Previewing {} of {} records", rows.min(req.data.len()), req.data.len());
+    
+    match run_blocking_with_timeout(move || preview_html(req, rows)).await {
+        Ok(html) => Ok(warp::reply::html(html)),
+        Err(e) => {
+            error!("❌ Preview failed: {}", e);
+            Err(to_rejection(e))
+        }
+    }
+}
+
+// Header detection dan konversi cell sama dengan generate, tapi hanya untuk N record pertama dan tanpa workbook
+fn preview_html(mut req: ExportRequest, rows: usize) -> anyhow::Result<String> {
+    req.data.truncate(rows);
+    validate_record_shapes(&req.data, req.options.headers.is_some(), None)?;
+    validate_schema(&req.data, &req.options, None)?;
+    let mut data = normalize_records(req.data, &req.options);
+    // normalize_arrays / unpivot bisa menambah row
+    data.truncate(rows);
+    let sheet = SheetSpec {
+        sheet_name: req.options.sheet_name.clone(),
+        data,
+        headers: req.options.headers.clone(),
+        tab_color: None,
+        index: None,
+    };
+    validate_sheets(std::slice::from_ref(&sheet), &req.options)?;
+    
+    let headers = resolve_headers(&sheet, &req.options);
+    let mut html = String::from("<table>\n<thead>\n<tr>");
+    for title in display_headers(&headers, &req.options)? {
+        html.push_str(&format!("<th>{}</th>", html_escape(&title)));
+    }
+    html.push_str("</tr>\n</thead>\n<tbody>\n");
+    for record in &sheet.data {
+        html.push_str("<tr>");
+        for cell in json_to_excel_row_optimized(record, &headers, &req.options) {
+            html.push_str(&format!("<td>{}</td>", html_escape(&cell_to_text(&cell))));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</tbody>\n</table>\n");
+    Ok(html)
+}
+
+fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn cell_type_name(cell: &CellValue) -> Option<&'static str> {
+    match cell {
+        CellValue::Empty => None,
+        CellValue::String(_) => Some("string"),
+        CellValue::Integer(_) => Some("integer"),
+        CellValue::Float(_) => Some("float"),
+        CellValue::Bool(_) => Some("bool"),
+        CellValue::Date(_) => Some("date"),
+        CellValue::DateTime(_) => Some("datetime"),
+    }
+}
+
+// Gabungkan tipe kolom: integer + float = float, tipe lain yang berbeda = mixed
+fn merge_column_type(current: &'static str, next: &'static str) -> &'static str {
+    match (current, next) {
+        ("empty", next) => next,
+        (current, next) if current == next => current,
+        ("integer", "float") | ("float", "integer") => "float",
+        _ => "mixed",
+    }
+}
+
+// Handler untuk generate Excel dari JSON yang di-host di URL lain
+async fn generate_excel_from_url_handler(req: UrlExportRequest) -> Result<impl warp::Reply, warp::Rejection> {
+    let slot = acquire_generation_slot().await.map_err(warp::reject::custom)?;
+    let start_time = std::time::Instant::now();
+    
+    info!("🌐 Starting Excel generation from URL: {}", req.url);
+    let output_format = req.options.format.unwrap_or_default();
+    let filename = sanitize_filename(&req.options.filename, output_format.extension()).map_err(warp::reject::custom)?;
+    
+    let result = GENERATION_SLOT.scope(slot, async {
+        let data = fetch_json_records(&req.url).await?;
+        info!("📥 Downloaded {} records", data.len());
+        let records = data.len();
+        let excel_data = generate_excel_file(ExportRequest { data, options: req.options }).await?;
+        anyhow::Ok((excel_data, records))
+    })
+    .await;
+    
+    match result {
+        Ok((excel_data, records_processed)) => {
+            let duration = start_time.elapsed();
+            info!("✅ Excel from URL generated successfully in {:?}", duration);
+            METRICS.record_success(duration, records_processed);
+            
+            Ok(file_reply(excel_data, output_format.content_type(), &filename))
+        }
+        Err(e) => {
+            error!("❌ Excel generation from URL failed: {}", e);
+            METRICS.record_failure(&e);
+            Err(to_rejection(e))
+        }
+    }
+}
+
+// Download JSON array dari URL. Untuk mencegah SSRF host harus ada di URL_ALLOWED_HOSTS dan redirect
+// tidak diikuti; ukuran download dibatasi URL_MAX_DOWNLOAD_MB.
+async fn fetch_json_records(url: &str) -> anyhow::Result<Vec<Value>> {
+    let parsed = reqwest::Url::parse(url)
+        .map_err(|e| ExcelError::InvalidOptions(format!("Invalid url '{}': {}", url, e)))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(ExcelError::UrlNotAllowed(format!("Unsupported url scheme '{}'", parsed.scheme())).into());
+    }
+    let host = parsed.host_str().unwrap_or_default();
+    if !url_allowed_hosts().iter().any(|allowed| allowed.eq_ignore_ascii_case(host)) {
+        return Err(ExcelError::UrlNotAllowed(format!("Host '{}' is not in URL_ALLOWED_HOSTS", host)).into());
+    }
+    
+    let client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(generation_timeout())
+        .build()?;
+    let mut response = client
+        .get(parsed)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map_err(|e| ExcelError::FetchFailed(format!("Failed to fetch '{}': {}", url, e)))?;
+    
+    let max_bytes = url_max_download_bytes();
+    let mut body: Vec<u8> = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| ExcelError::FetchFailed(format!("Failed to read '{}': {}", url, e)))?
+    {
+        if body.len() + chunk.len() > max_bytes {
+            return Err(ExcelError::DownloadTooLarge(format!(
+                "Response from '{}' exceeds {} bytes (URL_MAX_DOWNLOAD_MB)",
+                url, max_bytes
+            ))
+            .into());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    
+    serde_json::from_slice::<Vec<Value>>(&body).map_err(|e| {
+        ExcelError::FetchFailed(format!("Response from '{}' is not a JSON array of records: {}", url, e)).into()
+    })
+}
+
+// Host yang boleh diakses /generate-excel-from-url; kosong = endpoint menolak semua URL
+fn url_allowed_hosts() -> Vec<String> {
+    std::env::var("URL_ALLOWED_HOSTS")
+        .unwrap_or_default()
+        .split(',')
+        .map(|host| host.trim().to_string())
+        .filter(|host| !host.is_empty())
+        .collect()
+}
+
+fn url_max_download_bytes() -> usize {
+    std::env::var("URL_MAX_DOWNLOAD_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(100)
+        * 1024
+        * 1024
+}
+
+// Core function untuk generate Excel
+async fn generate_excel_file(req: ExportRequest) -> anyhow::Result<Vec<u8>> {
+    let sheet = request_sheet(&req.options, req.data);
+    generate_workbook(vec![sheet], req.options).await
+}
+
+fn request_sheet(options: &ExportOptions, data: Vec<Value>) -> SheetSpec {
+    SheetSpec {
+        sheet_name: options.sheet_name.clone(),
+        data,
+        headers: options.headers.clone(),
+        tab_color: None,
+        index: None,
+    }
+}
+
+// File hasil /generate-excel beserta index record yang dibuang skip_invalid_rows (None kalau option tidak aktif)
+#[derive(Debug, Clone)]
+struct GeneratedFile {
+    data: Vec<u8>,
+    skipped_rows: Option<Vec<usize>>,
+}
+
+impl GeneratedFile {
+    fn skipped_count(&self) -> usize {
+        self.skipped_rows.as_ref().map_or(0, Vec::len)
+    }
+}
+
+// generate_excel_file untuk /generate-excel: record invalid dibuang di blocking call yang sama dengan generate,
+// jadi tetap satu batas GENERATION_TIMEOUT_SECS dan cache Idempotency-Key tidak memvalidasi ulang
+async fn generate_file_skipping_invalid_rows(mut req: ExportRequest) -> anyhow::Result<GeneratedFile> {
+    run_blocking_with_timeout(move || {
+        let skipped_rows = if req.options.skip_invalid_rows.unwrap_or(false) {
+            let skipped = remove_invalid_rows(&mut req.data, &req.options)?;
+            if !skipped.is_empty() {
+                info!("⏭️ Skipped {} invalid rows", skipped.len());
+            }
+            Some(skipped)
+        } else {
+            None
+        };
+        let sheet = request_sheet(&req.options, req.data);
+        let data = build_workbook(vec![sheet], &req.options)?;
+        Ok(GeneratedFile { data, skipped_rows })
+    })
+    .await
+}
+
+// Generate CSV (RFC 4180) / TSV dari request yang sama dengan generate-excel. Field yang berisi delimiter,
+// quote, atau newline di-quote (termasuk tab di TSV), jadi value tetap utuh saat dibaca ulang
+async fn generate_csv_file(req: ExportRequest, format: TextFormat) -> anyhow::Result<Vec<u8>> {
+    run_blocking_with_timeout(move || write_csv(req, format)).await
+}
+
+fn write_csv(req: ExportRequest, format: TextFormat) -> anyhow::Result<Vec<u8>> {
+    validate_record_shapes(&req.data, req.options.headers.is_some(), None)?;
+    validate_schema(&req.data, &req.options, None)?;
+    let sheet = SheetSpec {
+        sheet_name: req.options.sheet_name.clone(),
+        data: normalize_records(req.data, &req.options),
+        headers: req.options.headers.clone(),
+        tab_color: None,
+        index: None,
+    };
+    validate_sheets(std::slice::from_ref(&sheet), &req.options)?;
+
+    let headers = resolve_headers(&sheet, &req.options);
+    info!("📊 Detected {} columns: {:?}", headers.len(), headers);
+    validate_column_count(headers.len(), &req.options, format.extension())?;
+    log_column_decisions(&sheet, &headers, &req.options);
+
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(format.delimiter())
+        .from_writer(Vec::new());
+    writer.write_record(display_headers(&headers, &req.options)?)?;
+    for record in &sheet.data {
+        let row = json_to_excel_row_optimized(record, &headers, &req.options);
+        writer.write_record(row.iter().map(cell_to_text))?;
+    }
+
+    let csv_data = writer.into_inner().map_err(|e| anyhow::anyhow!("Failed to flush {:?}: {}", format, e))?;
+    info!("✅ {:?} file generated, size: {} bytes", format, csv_data.len());
+    Ok(csv_data)
+}
+
+// Generate satu workbook berisi satu atau lebih worksheet, dibatasi GENERATION_TIMEOUT_SECS
+async fn generate_workbook(sheets: Vec<SheetSpec>, options: ExportOptions) -> anyhow::Result<Vec<u8>> {
+    run_blocking_with_timeout(move || build_workbook(sheets, &options)).await
+}
+
+// Validasi, normalisasi, dan tulis workbook (sinkron, dipanggil di blocking pool)
+fn build_workbook(sheets: Vec<SheetSpec>, options: &ExportOptions) -> anyhow::Result<Vec<u8>> {
+    // Dicek sebelum normalize_arrays supaya index di pesan error sesuai request
+    for sheet in &sheets {
+        let has_headers = sheet.headers.is_some() || options.headers.is_some();
+        validate_record_shapes(&sheet.data, has_headers, sheet.sheet_name.as_deref())?;
+        validate_schema(&sheet.data, options, sheet.sheet_name.as_deref())?;
+    }
+    let sheets: Vec<SheetSpec> = order_sheets(sheets)?
+        .into_iter()
+        .map(|sheet| SheetSpec { data: normalize_records(sheet.data, options), ..sheet })
+        .collect();
+    let sheets = split_overflowing_sheets(sheets, options, sheet_row_capacity(options))?;
+    if let Some(job) = current_job() {
+        job.total.store(sheets.iter().map(|sheet| sheet.data.len()).sum(), Ordering::SeqCst);
+    }
+    match options.format.unwrap_or_default() {
+        OutputFormat::Xlsx => write_workbook(&sheets, options),
+        OutputFormat::Ods => write_ods_workbook(&sheets, options),
+    }
+}
+
+// Urutkan sheet sesuai SheetSpec.index: sheet dengan index ditaruh di posisi tersebut, sisanya mengisi posisi
+// yang kosong sesuai urutan array. Index kembar atau di luar jumlah sheet ditolak
+fn order_sheets(sheets: Vec<SheetSpec>) -> Result<Vec<SheetSpec>, ExcelError> {
+    if sheets.iter().all(|sheet| sheet.index.is_none()) {
+        return Ok(sheets);
+    }
+    let total = sheets.len();
+    let mut slots: Vec<Option<SheetSpec>> = (0..total).map(|_| None).collect();
+    let mut unindexed = Vec::new();
+    for sheet in sheets {
+        let Some(index) = sheet.index else {
+            unindexed.push(sheet);
+            continue;
+        };
+        let name = sheet.sheet_name.clone().unwrap_or_default();
+        match slots.get_mut(index) {
+            None => {
+                return Err(ExcelError::InvalidOptions(format!(
+                    "Sheet '{}' has index {}, but the request only has {} sheets (index is 0-based)",
+                    name, index, total
+                )));
+            }
+            Some(Some(existing)) => {
+                return Err(ExcelError::InvalidOptions(format!(
+                    "Sheets '{}' and '{}' both have index {}",
+                    existing.sheet_name.as_deref().unwrap_or_default(), name, index
+                )));
+            }
+            Some(slot) => *slot = Some(sheet),
+        }
+    }
+    let mut unindexed = unindexed.into_iter();
+    Ok(slots
+        .into_iter()
+        .filter_map(|slot| slot.or_else(|| unindexed.next()))
+        .collect())
+}
+
+// Jumlah record yang muat di satu sheet: batas row Excel dikurangi title, row grup, header, dan footer summaries
+fn sheet_row_capacity(options: &ExportOptions) -> usize {
+    let footer_rows = u32::from(options.summaries.is_some());
+    EXCEL_MAX_ROWS.saturating_sub(SheetLayout::new(options).first_data_row() + footer_rows) as usize
+}
+
+// Sheet dengan record lebih dari capacity ditolak, atau dengan on_row_overflow "split" dipecah jadi beberapa sheet
+// berurutan. Sheet tanpa nama tetap tanpa nama (jadi Sheet1, Sheet2, ...), sheet bernama dilanjutkan "Data (2)", dst.
+fn split_overflowing_sheets(sheets: Vec<SheetSpec>, options: &ExportOptions, capacity: usize) -> Result<Vec<SheetSpec>, ExcelError> {
+    let split = options.on_row_overflow.unwrap_or_default() == RowOverflow::Split;
+    let mut result = Vec::with_capacity(sheets.len());
+    for mut sheet in sheets {
+        if sheet.data.len() <= capacity {
+            result.push(sheet);
+            continue;
+        }
+        if !split || capacity == 0 {
+            return Err(ExcelError::TooManyRows(format!(
+                "Sheet '{}' has {} rows, but only {} fit below the header on one Excel sheet; set options.on_row_overflow to \"split\" to continue on additional sheets",
+                sheet.sheet_name.as_deref().unwrap_or("default"), sheet.data.len(), capacity
+            )));
+        }
+        let parts = sheet.data.len().div_ceil(capacity);
+        info!("✂️ Splitting {} rows across {} sheets ({} rows per sheet)", sheet.data.len(), parts, capacity);
+        // Header dideteksi dari seluruh data supaya semua sheet lanjutan punya kolom yang sama
+        sheet.headers = Some(resolve_headers(&sheet, options));
+        // split_off dari belakang supaya setiap potongan hanya disalin sekali
+        let mut chunks: Vec<Vec<Value>> = (1..parts).rev().map(|part| sheet.data.split_off(part * capacity)).collect();
+        chunks.reverse();
+        let continuations: Vec<SheetSpec> = chunks
+            .into_iter()
+            .enumerate()
+            .map(|(part, data)| SheetSpec {
+                sheet_name: sheet.sheet_name.as_deref().map(|name| continuation_sheet_name(name, part + 2)),
+                data,
+                headers: sheet.headers.clone(),
+                tab_color: sheet.tab_color.clone(),
+                index: None,
+            })
+            .collect();
+        result.push(sheet);
+        result.extend(continuations);
+    }
+    Ok(result)
+}
+
+// "Data" jadi "Data (2)"; nama dasar dipotong supaya tetap dalam batas 31 karakter nama sheet Excel
+fn continuation_sheet_name(name: &str, part: usize) -> String {
+    let suffix = format!(" ({})", part);
+    let base: String = name.chars().take(31 - suffix.chars().count()).collect();
+    format!("{}{}", base, suffix)
+}
+
+// Jalankan pekerjaan sinkron (xlsxwriter + filesystem) di blocking thread pool supaya timeout bisa berjalan.
+// Thread blocking tidak bisa dihentikan paksa: setelah timeout hasilnya dibuang, temp file tetap dihapus TempFile,
+// dan GenerationSlot baru dilepas saat thread selesai supaya MAX_CONCURRENT tetap menghitung pekerjaan itu.
+async fn run_blocking_with_timeout<T, F>(work: F) -> anyhow::Result<T>
+where
+    F: FnOnce() -> anyhow::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let timeout = generation_timeout();
+    match tokio::time::timeout(timeout, spawn_blocking_with_request_id(work)).await {
+        Ok(joined) => joined.map_err(|e| anyhow::anyhow!("Generation task failed: {}", e))?,
+        Err(_) => Err(ExcelError::Timeout(format!(
+            "Generation exceeded {} seconds (GENERATION_TIMEOUT_SECS)",
+            timeout.as_secs()
+        ))
+        .into()),
+    }
+}
+
+// spawn_blocking yang membawa RequestContext ke thread blocking, supaya log di dalam write_sheet dkk tetap
+// berlabel dan X-Debug tetap berlaku. GenerationSlot (kalau ada) dipegang sampai work selesai
+fn spawn_blocking_with_request_id<T, F>(work: F) -> tokio::task::JoinHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let request_context = current_request_context();
+    let slot = GENERATION_SLOT.try_with(std::sync::Arc::clone).ok();
+    tokio::task::spawn_blocking(move || {
+        BLOCKING_REQUEST_CONTEXT.with(|context| *context.borrow_mut() = request_context);
+        let result = work();
+        BLOCKING_REQUEST_CONTEXT.with(|context| *context.borrow_mut() = None);
+        drop(slot);
+        result
+    })
+}
+
+fn generation_timeout() -> std::time::Duration {
+    let secs = std::env::var("GENERATION_TIMEOUT_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .unwrap_or(60);
+    std::time::Duration::from_secs(secs)
+}
+
+fn write_workbook(sheets: &[SheetSpec], options: &ExportOptions) -> anyhow::Result<Vec<u8>> {
+    validate_sheets(sheets, options)?;
+
+    // Create workbook - xlsxwriter 0.6 belum bisa output ke memory, jadi tulis ke temp file.
+    // TempFile dideklarasikan sebelum workbook supaya workbook selesai di-drop dulu sebelum file dihapus.
+    let temp_file = TempFile::new("xlsx")?;
+    let temp_dir = temp_dir_path();
+    let workbook = Workbook::new_with_options(temp_file.path_str()?, false, temp_dir.to_str(), false)?;
+
+    let mut dictionary = Vec::new();
+    let mut sparklines = Vec::new();
+    for (idx, sheet) in sheets.iter().enumerate() {
+        let sheet_name = sheet
+            .sheet_name
+            .clone()
+            .unwrap_or_else(|| format!("Sheet{}", idx + 1));
+        let written = write_sheet(&workbook, &sheet_name, sheet, options)?;
+        if written.sparklines.iter().any(|group| !group.is_empty()) {
+            sparklines.push((idx, written.sparklines));
+        }
+        if let Some(columns) = written.dictionary {
+            dictionary.push((sheet_name, sheet.data.len(), columns));
+        }
+    }
+    if options.include_dictionary.unwrap_or(false) {
+        write_dictionary_sheet(&workbook, &dictionary, options)?;
+    }
+    
+    // Finalize workbook
+    info!("💾 Finalizing workbook...");
+    workbook.close()?;
+    
+    // Read file and return as bytes (temp file dihapus otomatis oleh TempFile)
+    let mut excel_data = std::fs::read(&temp_file.path)?;
+    if !sparklines.is_empty() {
+        excel_data = add_sparklines(excel_data, &sparklines)?;
+    }
+    if let Some(properties) = &options.properties {
+        excel_data = set_doc_properties(excel_data, properties)?;
+    }
+    
+    info!("✅ Excel file generated, size: {} bytes", excel_data.len());
+    Ok(excel_data)
+}
+
+// Generate ODS (OpenDocument) dengan header/row extraction yang sama dengan xlsx.
+// Opsi khusus xlsx yang tidak bisa ditulis writer ods ditolak (lihat ods_unsupported_options).
+fn write_ods_workbook(sheets: &[SheetSpec], options: &ExportOptions) -> anyhow::Result<Vec<u8>> {
+    validate_sheets(sheets, options)?;
+    let unsupported = ods_unsupported_options(sheets, options);
+    if !unsupported.is_empty() {
+        return Err(ExcelError::InvalidOptions(format!(
+            "{} not supported with format \"ods\"",
+            unsupported.join(", ")
+        ))
+        .into());
+    }
+
+    let mut workbook = spreadsheet_ods::WorkBook::default();
+    let mut header_style = spreadsheet_ods::CellStyle::new("header", &spreadsheet_ods::defaultstyles::DefaultFormat::default());
+    header_style.set_font_bold();
+    let header_style = workbook.add_cellstyle(header_style);
+
+    for (idx, sheet) in sheets.iter().enumerate() {
+        let sheet_name = sheet
+            .sheet_name
+            .clone()
+            .unwrap_or_else(|| format!("Sheet{}", idx + 1));
+        info!("📝 Creating ODS sheet: {}", sheet_name);
+        let headers = resolve_headers(sheet, options);
+        validate_column_count(headers.len(), options, &sheet_name)?;
+        log_column_decisions(sheet, &headers, options);
+        let mut ods_sheet = spreadsheet_ods::Sheet::new(sheet_name);
+
+        for (col, title) in display_headers(&headers, options)?.into_iter().enumerate() {
+            ods_sheet.set_styled_value(0, col as u32, title, &header_style);
+        }
+        for (row_idx, record) in sheet.data.iter().enumerate() {
+            let row = (row_idx + 1) as u32;
+            for (col, cell) in json_to_excel_row_optimized(record, &headers, options).into_iter().enumerate() {
+                let col = col as u32;
+                match cell {
+                    CellValue::Empty => {},
+                    CellValue::String(s) => ods_sheet.set_value(row, col, s),
+                    CellValue::Integer(i) => ods_sheet.set_value(row, col, i as f64),
+                    CellValue::Float(f) => ods_sheet.set_value(row, col, f),
+                    CellValue::Bool(b) => ods_sheet.set_value(row, col, b),
+                    CellValue::Date(d) => ods_sheet.set_value(row, col, d),
+                    CellValue::DateTime(dt) => ods_sheet.set_value(row, col, dt),
+                }
+            }
+        }
+        workbook.push_sheet(ods_sheet);
+    }
+
+    info!("💾 Finalizing ODS workbook...");
+    let ods_data = spreadsheet_ods::write_ods_buf(&mut workbook, Vec::new())?;
+    info!("✅ ODS file generated, size: {} bytes", ods_data.len());
+    Ok(ods_data)
+}
+
+// Opsi xlsx yang tidak bisa ditulis writer ods: yang menambah row, kolom, sheet, atau isi cell, dan styling /
+// layout khusus xlsx. Kalau diabaikan, file ods diam-diam berbeda dari xlsx-nya, jadi ditolak.
+// Opsi yang hanya mengubah value cell (column_types, round_decimals, bool_format, null_value, dst.) tetap berlaku;
+// recalc_on_load tidak dicek karena formula_columns sudah ditolak
+fn ods_unsupported_options(sheets: &[SheetSpec], options: &ExportOptions) -> Vec<&'static str> {
+    [
+        ("title", options.title.is_some()),
+        ("start_row", options.start_row.is_some()),
+        ("start_col", options.start_col.is_some()),
+        ("header_groups", options.header_groups.is_some()),
+        ("summaries", options.summaries.is_some()),
+        ("formula_columns", options.formula_columns.is_some()),
+        ("image_columns", options.image_columns.is_some()),
+        ("comments_field", options.comments_field.is_some()),
+        ("merge_column", options.merge_column.is_some()),
+        ("include_dictionary", options.include_dictionary.unwrap_or(false)),
+        ("sparkline_columns", options.sparkline_columns.is_some()),
+        ("auto_filter", options.auto_filter.unwrap_or(false)),
+        ("as_table", options.as_table.unwrap_or(false)),
+        ("freeze_header", options.freeze_header.unwrap_or(false)),
+        ("freeze_rows", options.freeze_rows.is_some()),
+        ("freeze_cols", options.freeze_cols.is_some()),
+        ("lock_header_and_id", options.lock_header_and_id.is_some()),
+        ("autofit", options.autofit.unwrap_or(false)),
+        ("default_column_width", options.default_column_width.is_some()),
+        ("column_widths", options.column_widths.is_some()),
+        ("hidden_columns", options.hidden_columns.is_some()),
+        ("row_height", options.row_height.is_some()),
+        ("header_row_height", options.header_row_height.is_some()),
+        ("header_style", options.header_style.is_some()),
+        ("cell_borders", options.cell_borders.unwrap_or(false)),
+        ("column_formats", options.column_formats.is_some()),
+        ("date_format", options.date_format.is_some()),
+        ("datetime_format", options.datetime_format.is_some()),
+        ("locale", options.locale.is_some()),
+        ("column_fonts", options.column_fonts.is_some()),
+        ("column_alignment", options.column_alignment.is_some()),
+        ("wrap_columns", options.wrap_columns.is_some()),
+        ("conditional_formats", options.conditional_formats.is_some()),
+        ("detect_urls", options.detect_urls.unwrap_or(false)),
+        ("tab_color", options.tab_color.is_some() || sheets.iter().any(|sheet| sheet.tab_color.is_some())),
+        ("rtl", options.rtl.unwrap_or(false)),
+        ("protect", options.protect.is_some()),
+        ("properties", options.properties.is_some()),
+    ]
+    .into_iter()
+    .filter_map(|(name, set)| set.then_some(name))
+    .collect()
+}
+
+// Baca NDJSON dari channel dan tulis baris per baris; return bytes xlsx dan jumlah rows
+fn write_ndjson_workbook(
+    mut rx: tokio::sync::mpsc::Receiver<bytes::Bytes>,
+    options: ExportOptions,
+) -> anyhow::Result<(Vec<u8>, usize)> {
+    let temp_file = TempFile::new("xlsx")?;
+    let temp_dir = temp_dir_path();
+    let workbook = Workbook::new_with_options(temp_file.path_str()?, false, temp_dir.to_str(), false)?;
+    
+    let sheet_name = options.sheet_name.clone().unwrap_or_else(|| "Sheet1".to_string());
+    info!("📝 Creating worksheet: {}", sheet_name);
+    let mut writer = NdjsonSheetWriter {
+        worksheet: workbook.add_worksheet(Some(&sheet_name))?,
+        headers: options.headers.clone(),
+        formats: None,
+        options,
+        rows: 0,
+        line_number: 0,
+        max_rows: max_rows(),
+        max_line_bytes: ndjson_max_line_bytes(),
+    };
+    
+    // Pecah chunk menjadi baris; sisa baris yang belum lengkap disimpan untuk chunk berikutnya.
+    // Sisa ini dibatasi NDJSON_MAX_LINE_MB supaya body tanpa newline tidak ditampung tanpa batas
+    let mut buffer: Vec<u8> = Vec::new();
+    while let Some(chunk) = rx.blocking_recv() {
+        buffer.extend_from_slice(&chunk);
+        let mut start = 0;
+        while let Some(pos) = buffer[start..].iter().position(|b| *b == b'\n') {
+            writer.write_line(&buffer[start..start + pos])?;
+            start += pos + 1;
+        }
+        buffer.drain(..start);
+        writer.check_line_length(buffer.len())?;
+    }
+    writer.write_line(&buffer)?;
+    
+    let rows = writer.rows;
+    if rows == 0 {
+        return Err(ExcelError::EmptyData("Stream contains no records".to_string()).into());
+    }
+    drop(writer);
+    
+    info!("💾 Finalizing workbook...");
+    workbook.close()?;
+    let excel_data = std::fs::read(&temp_file.path)?;
+    
+    info!("✅ Excel file generated, size: {} bytes", excel_data.len());
+    Ok((excel_data, rows))
+}
+
+// State penulisan NDJSON: header ditulis saat record pertama datang
+struct NdjsonSheetWriter<'a> {
+    worksheet: Worksheet<'a>,
+    headers: Option<Vec<String>>,
+    formats: Option<CellFormats>,
+    options: ExportOptions,
+    rows: usize,
+    line_number: usize,
+    max_rows: usize,
+    max_line_bytes: usize,
+}
+
+impl NdjsonSheetWriter<'_> {
+    // len = panjang baris berikutnya (line_number + 1), lengkap atau masih menunggu newline
+    fn check_line_length(&self, len: usize) -> Result<(), ExcelError> {
+        if len > self.max_line_bytes {
+            return Err(ExcelError::PayloadTooLarge(format!(
+                "Line {} exceeds {}MB (NDJSON_MAX_LINE_MB)",
+                self.line_number + 1,
+                self.max_line_bytes / (1024 * 1024)
+            )));
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> anyhow::Result<()> {
+        self.check_line_length(line.len())?;
+        self.line_number += 1;
+        let line = line.trim_ascii();
+        if line.is_empty() {
+            return Ok(());
+        }
+        
+        let record: Value = serde_json::from_slice(line).map_err(|e| {
+            ExcelError::InvalidRecord(format!("Line {}: invalid JSON: {}", self.line_number, e))
+        })?;
+        if !record.is_object() {
+            return Err(ExcelError::InvalidRecord(format!(
+                "Line {}: expected a JSON object",
+                self.line_number
+            ))
+            .into());
+        }
+        
+        self.rows += 1;
+        if self.rows > self.max_rows {
+            return Err(ExcelError::TooManyRows(format!(
+                "Stream exceeds maximum of {} rows (MAX_ROWS)",
+                self.max_rows
+            ))
+            .into());
+        }
+        
+        // Header dari X-Headers atau dari key record pertama
+        let headers = self
+            .headers
+            .get_or_insert_with(|| auto_detect_headers(std::slice::from_ref(&record), true));
+        if self.formats.is_none() {
+            info!("📊 Detected {} columns: {:?}", headers.len(), headers);
+            validate_column_count(headers.len(), &self.options, self.options.sheet_name.as_deref().unwrap_or("Sheet1"))?;
+            let header_format = header_format(self.options.header_style.as_ref())?;
+            // Record belum ada yang ditampung, jadi autofit (kalau aktif) hanya dari record pertama
+            let widths = column_widths(headers, headers, std::slice::from_ref(&record), &self.options);
+            for (col, (header, width)) in headers.iter().zip(widths).enumerate() {
+                self.worksheet.write_string(0, col as u16, header, Some(&header_format))?;
+                self.worksheet.set_column(col as u16, col as u16, width, None)?;
+            }
+            self.formats = Some(CellFormats::new(headers, &self.options));
+        }
+        let formats = self.formats.as_ref().expect("formats initialized with headers");
+        
+        let row = json_to_excel_row_optimized(&record, headers, &self.options);
+        for (col, cell_value) in row.iter().enumerate() {
+            write_cell(&mut self.worksheet, self.rows as u32, col as u16, cell_value, formats)?;
+        }
+        
+        if self.rows.is_multiple_of(10000) {
+            info!("📈 Progress: {} rows streamed", self.rows);
+        }
+        Ok(())
+    }
+}
+
+// Transformasi record sebelum header detection: normalize_arrays lalu unpivot
+fn normalize_records(data: Vec<Value>, options: &ExportOptions) -> Vec<Value> {
+    let data = normalize_array_field(data, options);
+    let data = match options.complex_value_mode {
+        Some(ComplexMode::Flatten) => data.into_iter().map(|record| flatten_record(record, options)).collect(),
+        _ => data,
+    };
+    match &options.unpivot {
+        Some(spec) => unpivot_records(data, spec),
+        None => data,
+    }
+}
+
+// complex_value_mode "flatten": {"address": {"city": "X"}} jadi {"address.city": "X"}, rekursif, di posisi key
+// aslinya. Object kosong tetap satu kolom kosong; comments_field tidak disentuh karena dibaca sebagai object
+fn flatten_record(record: Value, options: &ExportOptions) -> Value {
+    fn flatten_into(prefix: String, value: Value, out: &mut serde_json::Map<String, Value>) {
+        match value {
+            Value::Object(map) if !map.is_empty() => {
+                for (key, child) in map {
+                    flatten_into(format!("{}.{}", prefix, key), child, out);
+                }
+            }
+            Value::Object(_) => {
+                out.insert(prefix, Value::Null);
+            }
+            other => {
+                out.insert(prefix, other);
+            }
+        }
+    }
+
+    let Value::Object(map) = record else {
+        return record;
+    };
+    let mut flat = serde_json::Map::with_capacity(map.len());
+    for (key, value) in map {
+        if options.comments_field.as_ref() == Some(&key) {
+            flat.insert(key, value);
+        } else {
+            flatten_into(key, value, &mut flat);
+        }
+    }
+    Value::Object(flat)
+}
+
+// Wide to long: setiap value column jadi satu row {id_columns..., variable, value}. Kolom yang tidak ada
+// di record tetap menghasilkan row dengan value null, record bukan object dibiarkan
+fn unpivot_records(data: Vec<Value>, spec: &UnpivotSpec) -> Vec<Value> {
+    let variable_name = spec.variable_name.as_deref().unwrap_or("variable");
+    let value_name = spec.value_name.as_deref().unwrap_or("value");
+    let mut rows = Vec::with_capacity(data.len());
+    for record in data {
+        let Value::Object(mut record) = record else {
+            rows.push(record);
+            continue;
+        };
+        let value_columns: Vec<String> = match &spec.value_columns {
+            Some(columns) => columns.clone(),
+            None => record.keys().filter(|key| !spec.id_columns.contains(key)).cloned().collect(),
+        };
+        let ids: Vec<(String, Value)> = spec
+            .id_columns
+            .iter()
+            .map(|column| (column.clone(), record.get(column).cloned().unwrap_or(Value::Null)))
+            .collect();
+        for column in value_columns {
+            let mut row: serde_json::Map<String, Value> = ids.iter().cloned().collect();
+            let value = record.remove(&column).unwrap_or(Value::Null);
+            row.insert(variable_name.to_string(), Value::String(column));
+            row.insert(value_name.to_string(), value);
+            rows.push(Value::Object(row));
+        }
+    }
+    rows
+}
+
+// normalize_arrays: record dengan field array of objects dipecah jadi satu row per element (left join).
+// Kolom parent diulang, key element jadi kolom "<field>.<key>" di posisi field tersebut.
+// Array kosong / field tidak ada tetap menghasilkan satu row; element non-object ditulis di kolom <field>.
+fn normalize_array_field(data: Vec<Value>, options: &ExportOptions) -> Vec<Value> {
+    let Some(field) = &options.normalize_arrays else {
+        return data;
+    };
+
+    let mut rows = Vec::with_capacity(data.len());
+    for record in data {
+        let Value::Object(parent) = record else {
+            rows.push(record);
+            continue;
+        };
+        let children = match parent.get(field) {
+            Some(Value::Array(items)) if !items.is_empty() => items.clone(),
+            _ => vec![Value::Null],
+        };
+        for child in children {
+            let mut row = serde_json::Map::with_capacity(parent.len());
+            for (key, value) in &parent {
+                if key != field {
+                    row.insert(key.clone(), value.clone());
+                    continue;
+                }
+                match &child {
+                    Value::Object(child_map) => {
+                        for (child_key, child_value) in child_map {
+                            row.insert(format!("{}.{}", field, child_key), child_value.clone());
+                        }
+                    }
+                    Value::Null => {}
+                    other => {
+                        row.insert(field.clone(), other.clone());
+                    }
+                }
+            }
+            rows.push(Value::Object(row));
+        }
+    }
+    rows
+}
+
+// Setiap record harus object. Pengecualian: semua record scalar tanpa custom headers, ditulis di satu kolom "data"
+fn validate_record_shapes(data: &[Value], has_headers: bool, sheet_name: Option<&str>) -> Result<(), ExcelError> {
+    let scalar_layout = !has_headers && !data.iter().any(Value::is_object);
+    if scalar_layout {
+        return Ok(());
+    }
+    let Some((index, record)) = data.iter().enumerate().find(|(_, record)| !record.is_object()) else {
+        return Ok(());
+    };
+    let kind = match record {
+        Value::Null => "null",
+        Value::Bool(_) => "a boolean",
+        Value::Number(_) => "a number",
+        Value::String(_) => "a string",
+        Value::Array(_) => "an array",
+        Value::Object(_) => "an object",
+    };
+    Err(ExcelError::InvalidRecordShape(format!(
+        "{} is {}, expected a JSON object",
+        record_location(sheet_name, index), kind
+    )))
+}
+
+// Jumlah pelanggaran schema yang ditampilkan di pesan error
+const MAX_SCHEMA_VIOLATIONS: usize = 5;
+
+// Validasi setiap record terhadap options.schema; pesan error berisi beberapa pelanggaran pertama beserta path-nya
+fn validate_schema(data: &[Value], options: &ExportOptions, sheet_name: Option<&str>) -> Result<(), ExcelError> {
+    let Some(validator) = schema_validator(options)? else {
+        return Ok(());
+    };
+    let mut violations = Vec::new();
+    let mut total = 0;
+    for (index, record) in data.iter().enumerate() {
+        for error in validator.iter_errors(record) {
+            total += 1;
+            if violations.len() < MAX_SCHEMA_VIOLATIONS {
+                violations.push(format!("{}{}: {}", record_location(sheet_name, index), error.instance_path, error));
+            }
+        }
+    }
+    if total == 0 {
+        return Ok(());
+    }
+    let more = if total > violations.len() {
+        format!(" (and {} more)", total - violations.len())
+    } else {
+        String::new()
+    };
+    Err(ExcelError::SchemaViolation(format!(
+        "Schema validation failed: {}{}",
+        violations.join("; "),
+        more
+    )))
+}
+
+// Validator options.schema; None kalau schema tidak di-set, schema yang tidak valid jadi INVALID_OPTIONS
+fn schema_validator(options: &ExportOptions) -> Result<Option<jsonschema::Validator>, ExcelError> {
+    options
+        .schema
+        .as_ref()
+        .map(|schema| jsonschema::validator_for(schema).map_err(|e| ExcelError::InvalidOptions(format!("Invalid schema: {}", e))))
+        .transpose()
+}
+
+// skip_invalid_rows: buang record yang akan ditolak validate_record_shapes / validate_schema dan return index
+// aslinya (0-based). Schema yang tidak valid tetap error
+fn remove_invalid_rows(data: &mut Vec<Value>, options: &ExportOptions) -> Result<Vec<usize>, ExcelError> {
+    let scalar_layout = options.headers.is_none() && !data.iter().any(Value::is_object);
+    let validator = schema_validator(options)?;
+    let mut skipped = Vec::new();
+    let mut index = 0;
+    data.retain(|record| {
+        let valid = (scalar_layout || record.is_object()) && validator.as_ref().is_none_or(|validator| validator.is_valid(record));
+        if !valid {
+            skipped.push(index);
+        }
+        index += 1;
+        valid
+    });
+    Ok(skipped)
+}
+
+// Lokasi record untuk pesan error, mis. "data[1]" atau "sheet 'Unit' data[1]"
+fn record_location(sheet_name: Option<&str>, index: usize) -> String {
+    match sheet_name {
+        Some(name) => format!("sheet '{}' data[{}]", name, index),
+        None => format!("data[{}]", index),
+    }
+}
+
+// Validasi input sebelum generate: minimal satu sheet, data tidak kosong (kecuali allow_empty), dan nilai options
+fn validate_sheets(sheets: &[SheetSpec], options: &ExportOptions) -> anyhow::Result<()> {
+    if sheets.is_empty() {
+        return Err(ExcelError::EmptyData("At least one sheet is required".to_string()).into());
+    }
+    let total_rows: usize = sheets.iter().map(|sheet| sheet.data.len()).sum();
+    let max_rows = max_rows();
+    if total_rows > max_rows {
+        return Err(ExcelError::TooManyRows(format!(
+            "Request contains {} rows, maximum allowed is {} (MAX_ROWS)",
+            total_rows, max_rows
+        ))
+        .into());
+    }
+    if !options.allow_empty.unwrap_or(false) {
+        if let Some(sheet) = sheets.iter().find(|sheet| sheet.data.is_empty()) {
+            let name = sheet.sheet_name.as_deref().unwrap_or("default");
+            return Err(ExcelError::EmptyData(format!(
+                "Sheet '{}' has no records; set options.allow_empty to true to export headers only",
+                name
+            ))
+            .into());
+        }
+    }
+    if let Some(locale) = &options.locale {
+        if !SUPPORTED_LOCALES.iter().any(|supported| supported.eq_ignore_ascii_case(locale)) {
+            return Err(ExcelError::InvalidOptions(format!(
+                "Unsupported locale '{}': expected one of {}",
+                locale,
+                SUPPORTED_LOCALES.join(", ")
+            ))
+            .into());
+        }
+    }
+    if options.include_dictionary.unwrap_or(false)
+        && sheets.iter().filter_map(|sheet| sheet.sheet_name.as_deref()).any(|name| name.eq_ignore_ascii_case(DICTIONARY_SHEET_NAME))
+    {
+        return Err(ExcelError::InvalidOptions(format!(
+            "Sheet name '{}' is reserved when include_dictionary is true",
+            DICTIONARY_SHEET_NAME
+        ))
+        .into());
+    }
+    // Warna dicek di awal supaya error muncul sebelum workbook mulai ditulis
+    for hex in sheets.iter().filter_map(|sheet| sheet.tab_color.as_ref()).chain(&options.tab_color) {
+        parse_hex_color(hex)?;
+    }
+    if let Some(width) = options.default_column_width {
+        validate_column_width("default_column_width", width)?;
+    }
+    for (header, width) in options.column_widths.iter().flatten() {
+        validate_column_width(&format!("column_widths.{}", header), *width)?;
+    }
+    for spec in options.conditional_formats.iter().flatten() {
+        conditional_format(spec)?;
+    }
+    for (header, font) in options.column_fonts.iter().flatten() {
+        validate_font(header, font)?;
+    }
+    for (name, height) in [("row_height", options.row_height), ("header_row_height", options.header_row_height)] {
+        if height.is_some_and(|height| !(height > 0.0 && height <= MAX_ROW_HEIGHT)) {
+            return Err(ExcelError::InvalidOptions(format!(
+                "Invalid {} {}: must be greater than 0 and at most {}",
+                name, height.unwrap_or_default(), MAX_ROW_HEIGHT
+            ))
+            .into());
+        }
+    }
+    // xlsxwriter panic kalau password mengandung NUL
+    if options.protect.as_ref().and_then(|protect| protect.password.as_ref()).is_some_and(|password| password.contains('\0')) {
+        return Err(ExcelError::InvalidOptions("protect.password must not contain NUL characters".to_string()).into());
+    }
+    if options.start_row.is_some_and(|row| row >= EXCEL_MAX_ROWS) || options.start_col.is_some_and(|col| col >= EXCEL_MAX_COLS) {
+        return Err(ExcelError::InvalidOptions(format!(
+            "start_row must be below {} and start_col below {}",
+            EXCEL_MAX_ROWS, EXCEL_MAX_COLS
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+// Batas jumlah rows per request supaya tidak OOM (default 1 juta)
+fn max_rows() -> usize {
+    std::env::var("MAX_ROWS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(1_000_000)
+}
+
+// Batas jumlah kolom per sheet (default dan maksimal 16384, batas Excel)
+fn max_columns() -> usize {
+    std::env::var("MAX_COLUMNS")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|limit| *limit > 0)
+        .map_or(EXCEL_MAX_COLS as usize, |limit| limit.min(EXCEL_MAX_COLS as usize))
+}
+
+// Dicek setelah headers diketahui; start_col ikut dihitung karena kolom terakhir harus masih di dalam sheet
+fn validate_column_count(columns: usize, options: &ExportOptions, sheet_name: &str) -> Result<(), ExcelError> {
+    let max_columns = max_columns();
+    if columns > max_columns {
+        return Err(ExcelError::TooManyColumns(format!(
+            "Sheet '{}' has {} columns, maximum allowed is {} (MAX_COLUMNS)",
+            sheet_name, columns, max_columns
+        )));
+    }
+    let first_col = options.start_col.unwrap_or(0) as usize;
+    if first_col + columns > EXCEL_MAX_COLS as usize {
+        return Err(ExcelError::TooManyColumns(format!(
+            "Sheet '{}' has {} columns starting at column {}, beyond Excel's limit of {} columns",
+            sheet_name, columns, first_col + 1, EXCEL_MAX_COLS
+        )));
+    }
+    Ok(())
+}
+
+fn validate_column_width(name: &str, width: f64) -> Result<(), ExcelError> {
+    if !(width > 0.0 && width <= MAX_COLUMN_WIDTH) {
+        return Err(ExcelError::InvalidOptions(format!(
+            "Invalid {} {}: must be greater than 0 and at most {}",
+            name, width, MAX_COLUMN_WIDTH
+        )));
+    }
+    Ok(())
+}
+
+// Tinggi row maksimal di Excel (point)
+const MAX_ROW_HEIGHT: f64 = 409.0;
+
+// Batas Excel: nama font maksimal 31 karakter, ukuran 1-409 pt
+const MAX_FONT_NAME_LEN: usize = 31;
+const MAX_FONT_SIZE: f64 = 409.0;
+
+fn validate_font(header: &str, font: &FontSpec) -> Result<(), ExcelError> {
+    if let Some(name) = &font.name {
+        if name.trim().is_empty() || name.chars().count() > MAX_FONT_NAME_LEN || name.contains('\0') {
+            return Err(ExcelError::InvalidOptions(format!(
+                "Invalid column_fonts.{}.name '{}': must be 1-{} characters",
+                header, name, MAX_FONT_NAME_LEN
+            )));
+        }
+    }
+    if let Some(size) = font.size {
+        if !(1.0..=MAX_FONT_SIZE).contains(&size) {
+            return Err(ExcelError::InvalidOptions(format!(
+                "Invalid column_fonts.{}.size {}: must be between 1 and {}",
+                header, size, MAX_FONT_SIZE
+            )));
+        }
+    }
+    Ok(())
+}
+
+// Judul kolom untuk header row; value tetap diambil dari key asli
+fn display_header<'a>(header: &'a str, options: &'a ExportOptions) -> &'a str {
+    options
+        .header_aliases
+        .as_ref()
+        .and_then(|aliases| aliases.get(header))
+        .map_or(header, String::as_str)
+}
+
+// Judul semua kolom. Judul kembar (headers ["a", "a"] atau dua key dengan alias yang sama) ditolak,
+// atau dengan dedupe_headers diberi suffix _2, _3, ... yang belum dipakai kolom lain
+fn display_headers(headers: &[String], options: &ExportOptions) -> Result<Vec<String>, ExcelError> {
+    let titles: Vec<&str> = headers.iter().map(|header| display_header(header, options)).collect();
+    let dedupe = options.dedupe_headers.unwrap_or(false);
+    let mut used: std::collections::HashSet<String> = std::collections::HashSet::new();
+    let mut result = Vec::with_capacity(titles.len());
+    for (col, title) in titles.iter().enumerate() {
+        if used.insert(title.to_string()) {
+            result.push(title.to_string());
+            continue;
+        }
+        if !dedupe {
+            let first = titles.iter().position(|other| other == title).unwrap_or_default();
+            return Err(ExcelError::InvalidOptions(format!(
+                "Duplicate header '{}' in columns {} ('{}') and {} ('{}'); rename it with header_aliases or set dedupe_headers to true",
+                title, first + 1, headers[first], col + 1, headers[col]
+            )));
+        }
+        // Suffix juga tidak boleh sama dengan judul kolom yang muncul belakangan
+        let deduped = (2..)
+            .map(|counter| format!("{}_{}", title, counter))
+            .find(|candidate| !used.contains(candidate) && !titles.contains(&candidate.as_str()))
+            .unwrap_or_default();
+        used.insert(deduped.clone());
+        result.push(deduped);
+    }
+    Ok(result)
+}
+
+// Panjang maksimal satu baris NDJSON di /generate-excel-stream (default 16MB)
+fn ndjson_max_line_bytes() -> usize {
+    std::env::var("NDJSON_MAX_LINE_MB")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(16)
+        * 1024
+        * 1024
+}
+
+// Custom headers kalau ada, selain itu auto-detect dari data.
+// Custom headers adalah projection: hanya key tersebut yang di-export, sesuai urutannya;
+// key yang tidak ada di record jadi kolom kosong (record[header] == Null), bukan error.
+fn resolve_headers(sheet: &SheetSpec, options: &ExportOptions) -> Vec<String> {
+    if let Some(custom_headers) = &sheet.headers {
+        custom_headers.clone()
+    } else {
+        let mut headers = auto_detect_headers(&sheet.data, options.preserve_order.unwrap_or(true));
+        if let Some(comments_field) = &options.comments_field {
+            headers.retain(|header| header != comments_field);
+        }
+        headers
+    }
+}
+
+// Convert chrono datetime ke DateTime xlsxwriter
+fn excel_datetime(dt: &chrono::NaiveDateTime) -> DateTime {
+    DateTime::new(
+        dt.year() as i16,
+        dt.month() as i8,
+        dt.day() as i8,
+        dt.hour() as i8,
+        dt.minute() as i8,
+        dt.second() as f64 + dt.nanosecond() as f64 / 1_000_000_000.0,
+    )
+}
+
+// Directory untuk temp file: TEMP_DIR, kalau tidak di-set mengikuti TMPDIR (default /tmp)
+fn temp_dir_path() -> std::path::PathBuf {
+    std::env::var_os("TEMP_DIR")
+        .filter(|dir| !dir.is_empty())
+        .map(std::path::PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+// Temp file yang otomatis dihapus saat keluar scope, termasuk di jalur error
+struct TempFile {
+    path: std::path::PathBuf,
+}
+
+impl TempFile {
+    // File dibuat dulu dengan mode 0600 (Unix); xlsxwriter menulis ulang file yang sama tanpa mengubah
+    // permission, jadi isi export tidak bisa dibaca user lain di host yang sama
+    fn new(extension: &str) -> anyhow::Result<Self> {
+        let path = temp_dir_path().join(format!("temp_{}.{}", uuid::Uuid::new_v4(), extension));
+        let mut open_options = std::fs::OpenOptions::new();
+        open_options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut open_options, 0o600);
+        open_options
+            .open(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to create temp file {:?}: {}", path, e))?;
+        Ok(TempFile { path })
+    }
+
+    fn path_str(&self) -> anyhow::Result<&str> {
+        self.path
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("Temp path is not valid UTF-8: {:?}", self.path))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+// Posisi baris di worksheet. Semua perhitungan row index lewat sini supaya baris tambahan
+// di atas header (title, header_groups) otomatis menggeser header dan data.
+#[derive(Debug, Clone, Copy)]
+struct SheetLayout {
+    // Row pertama blok (title kalau ada, lalu row grup, lalu header)
+    start_row: u32,
+    // Row header_groups; Some kalau header_groups di-set
+    group_row: Option<u32>,
+    header_row: u32,
+    first_col: u16,
+}
+
+impl SheetLayout {
+    fn new(options: &ExportOptions) -> Self {
+        let start_row = options.start_row.unwrap_or(0);
+        let mut next_row = start_row;
+        if options.title.is_some() {
+            next_row += 1;
+        }
+        let group_row = options.header_groups.is_some().then_some(next_row);
+        if group_row.is_some() {
+            next_row += 1;
+        }
+        SheetLayout {
+            start_row,
+            group_row,
+            header_row: next_row,
+            first_col: options.start_col.unwrap_or(0),
+        }
+    }
+
+    // Kolom worksheet untuk header ke-index (0-based)
+    fn col(self, index: usize) -> u16 {
+        self.first_col + index as u16
+    }
+
+    // Kolom terakhir untuk sejumlah columns (minimal 1)
+    fn last_col(self, columns: usize) -> u16 {
+        self.col(columns.saturating_sub(1))
+    }
+
+    fn first_data_row(self) -> u32 {
+        self.header_row + 1
+    }
+
+    // Row worksheet untuk record ke-index (0-based)
+    fn data_row(self, index: usize) -> u32 {
+        self.first_data_row() + index as u32
+    }
+
+    // Row terakhir range header + total_rows data
+    fn last_row(self, total_rows: usize) -> u32 {
+        self.header_row + total_rows as u32
+    }
+}
+
+// Range index header (first, last) per grup. Grup harus menunjuk kolom yang di-export, muat di dalam
+// headers, dan tidak saling tumpang tindih
+fn header_group_ranges<'a>(headers: &[String], options: &'a ExportOptions) -> Result<Vec<(usize, usize, &'a str)>, ExcelError> {
+    let mut ranges: Vec<(usize, usize, &str)> = Vec::new();
+    for group in options.header_groups.iter().flatten() {
+        let first = headers.iter().position(|header| *header == group.start_column).ok_or_else(|| {
+            ExcelError::InvalidOptions(format!(
+                "header_groups '{}': start_column '{}' is not one of the exported columns",
+                group.label, group.start_column
+            ))
+        })?;
+        if group.span == 0 || first + group.span > headers.len() {
+            return Err(ExcelError::InvalidOptions(format!(
+                "header_groups '{}': span {} from '{}' must be at least 1 and fit in the {} exported columns",
+                group.label, group.span, group.start_column, headers.len()
+            )));
+        }
+        let last = first + group.span - 1;
+        if let Some((_, _, other)) = ranges.iter().find(|(start, end, _)| first <= *end && *start <= last) {
+            return Err(ExcelError::InvalidOptions(format!(
+                "header_groups '{}' overlaps header_groups '{}'",
+                group.label, other
+            )));
+        }
+        ranges.push((first, last, &group.label));
+    }
+    Ok(ranges)
+}
+
+// Row grup di atas header: label di-merge selebar span-nya, kolom tanpa grup dibiarkan kosong dengan style header
+fn write_header_groups(
+    worksheet: &mut Worksheet,
+    layout: SheetLayout,
+    headers: &[String],
+    options: &ExportOptions,
+    header_format: &Format,
+) -> anyhow::Result<()> {
+    let Some(row) = layout.group_row else {
+        return Ok(());
+    };
+    let ranges = header_group_ranges(headers, options)?;
+    for col in 0..headers.len() {
+        if !ranges.iter().any(|(first, last, _)| (*first..=*last).contains(&col)) {
+            worksheet.write_blank(row, layout.col(col), Some(header_format))?;
+        }
+    }
+    for (first, last, label) in ranges {
+        if first == last {
+            worksheet.write_string(row, layout.col(first), label, Some(header_format))?;
+        } else {
+            worksheet.merge_range(row, layout.col(first), row, layout.col(last), label, Some(header_format))?;
+        }
+    }
+    Ok(())
+}
+
+fn log_column_decisions(sheet: &SheetSpec, headers: &[String], options: &ExportOptions) {
+    if log::log_enabled!(log::Level::Debug) {
+        for header in headers {
+            debug!("Column '{}': {}", header, column_type_decision(sheet, header, options));
+        }
+    }
+}
+
+// Penjelasan tipe kolom untuk log debug (X-Debug): override dari options, atau tipe hasil inference
+// di sample rows (sama dengan /inspect)
+fn column_type_decision(sheet: &SheetSpec, header: &str, options: &ExportOptions) -> String {
+    if let Some(column_type) = options.column_types.as_ref().and_then(|types| types.get(header)) {
+        return format!("column_types {:?}", column_type);
+    }
+    if options.numeric_columns.iter().flatten().any(|column| column == header) {
+        return "numeric_columns".to_string();
+    }
+    let headers = [header.to_string()];
+    let inferred = sheet
+        .data
+        .iter()
+        .take(AUTOFIT_SAMPLE_ROWS)
+        .filter_map(|record| cell_type_name(&json_to_excel_row_optimized(record, &headers, options)[0]))
+        .fold("empty", merge_column_type);
+    format!("inferred {} (first {} rows)", inferred, AUTOFIT_SAMPLE_ROWS.min(sheet.data.len()))
+}
+
+// Title bold ukuran 14 di row pertama blok, di-merge selebar semua kolom
+fn write_title(worksheet: &mut Worksheet, layout: SheetLayout, title: &str, columns: usize) -> anyhow::Result<()> {
+    let mut format = Format::new();
+    format.set_bold().set_font_size(14.0);
+    let (row, first_col) = (layout.start_row, layout.first_col);
+    if columns > 1 {
+        worksheet.merge_range(row, first_col, row, layout.last_col(columns), title, Some(&format))?;
+    } else {
+        worksheet.write_string(row, first_col, title, Some(&format))?;
+    }
+    Ok(())
+}
+
+// Hasil write_sheet yang masih diproses setelah sheet selesai ditulis
+struct WrittenSheet {
+    // Ringkasan kolom untuk sheet Dictionary kalau include_dictionary aktif
+    dictionary: Option<Vec<ColumnReport>>,
+    // Satu grup per sparkline_columns, ditambahkan ke XML sheet setelah workbook ditutup
+    sparklines: Vec<Vec<Sparkline>>,
+}
+
+// Tulis headers dan data rows ke satu worksheet
+fn write_sheet(
+    workbook: &Workbook,
+    sheet_name: &str,
+    sheet: &SheetSpec,
+    options: &ExportOptions,
+) -> anyhow::Result<WrittenSheet> {
+    info!("📝 Creating worksheet: {}", sheet_name);
+    let mut worksheet = workbook.add_worksheet(Some(sheet_name))?;
+    
+    // Auto-detect headers atau gunakan custom headers
+    let headers = resolve_headers(sheet, options);
+    let titles = display_headers(&headers, options)?;
+    validate_column_count(headers.len(), options, sheet_name)?;
+    
+    info!("📊 Detected {} columns: {:?}", headers.len(), headers);
+    log_column_decisions(sheet, &headers, options);
+    
+    if let Some(hex) = sheet.tab_color.as_ref().or(options.tab_color.as_ref()) {
+        worksheet.set_tab_color(FormatColor::Custom(parse_hex_color(hex)?));
+    }
+    if options.rtl.unwrap_or(false) {
+        worksheet.set_right_to_left();
+    }
+    if let Some(protect) = &options.protect {
+        worksheet.protect(protect.password.as_deref().unwrap_or(""), &worksheet_protection(protect));
+    }
+    
+    let layout = SheetLayout::new(options);
+    if let Some(title) = &options.title {
+        write_title(&mut worksheet, layout, title, headers.len())?;
+    }
+    
+    // Write headers
+    let header_format = header_format(options.header_style.as_ref())?;
+    for (col, title) in titles.iter().enumerate() {
+        worksheet.write_string(layout.header_row, layout.col(col), title, Some(&header_format))?;
+    }
+    if let Some(height) = options.header_row_height {
+        worksheet.set_row(layout.header_row, height, None)?;
+    }
+    write_header_groups(&mut worksheet, layout, &headers, options, &header_format)?;
+    
+    // Freeze baris/kolom supaya tetap terlihat saat scroll; freeze_header = freeze sampai header row (termasuk title).
+    // lock_header_and_id = freeze_header + semua kolom sampai kolom ID. freeze_rows / freeze_cols tetap menimpa.
+    let id_column = match &options.lock_header_and_id {
+        Some(id) => match headers.iter().position(|header| header == id) {
+            Some(col) => Some(layout.col(col)),
+            None => {
+                return Err(ExcelError::InvalidOptions(format!(
+                    "lock_header_and_id '{}' is not one of the exported columns",
+                    id
+                ))
+                .into());
+            }
+        },
+        None => None,
+    };
+    let freeze_header = options.freeze_header.unwrap_or(false) || id_column.is_some();
+    let default_freeze_rows = if freeze_header { layout.first_data_row() } else { 0 };
+    let freeze_rows = options.freeze_rows.unwrap_or(default_freeze_rows);
+    let freeze_cols = options.freeze_cols.unwrap_or(id_column.map_or(0, |col| col + 1));
+    if freeze_rows > 0 || freeze_cols > 0 {
+        worksheet.freeze_panes(freeze_rows, freeze_cols);
+    }
+    
+    let widths = column_widths(&headers, &titles, &sheet.data, options);
+    // column_widths menimpa autofit / default untuk kolom yang disebut
+    let mut widths: Vec<f64> = widths
+        .iter()
+        .enumerate()
+        .map(|(col, width)| {
+            *options
+                .column_widths
+                .as_ref()
+                .and_then(|column_widths| column_widths.get(&headers[col]))
+                .unwrap_or(width)
+        })
+        .collect();
+    for (col, width) in widths.iter().enumerate() {
+        worksheet.set_column(layout.col(col), layout.col(col), *width, None)?;
+    }
+    
+    let cell_formats = CellFormats::new(&headers, options);
+    let header_columns: HashMap<&str, u16> = headers
+        .iter()
+        .enumerate()
+        .map(|(col, header)| (header.as_str(), layout.col(col)))
+        .collect();
+    
+    // Write data rows (optimized batch processing)
+    info!("📝 Writing {} data rows...", sheet.data.len());
+    
+    // Process data in chunks for better memory management
+    let chunk_size = std::env::var("EXCEL_CHUNK_SIZE")
+        .unwrap_or_else(|_| "5000".to_string())
+        .parse::<usize>()
+        .unwrap_or(5000);
+    let total_rows = sheet.data.len();
+    
+    info!("🔧 Using chunk size: {} for {} total rows", chunk_size, total_rows);
+    let parallel = options.parallel.unwrap_or(false);
+    let mut summaries = summary_accumulators(&headers, options)?;
+    let formulas = formula_templates(&headers, layout, options)?;
+    let image_columns: Vec<bool> = headers
+        .iter()
+        .map(|header| options.image_columns.iter().flatten().any(|column| column == header))
+        .collect();
+    // Lebar gambar terbesar per kolom (pixel), untuk melebarkan kolom setelah semua row ditulis
+    let mut image_widths = vec![0.0_f64; headers.len()];
+    let mut dictionary: Option<Vec<ColumnReport>> = options
+        .include_dictionary
+        .unwrap_or(false)
+        .then(|| headers.iter().map(|header| ColumnReport::new(header)).collect());
+    let sparkline_columns = sparkline_columns(&headers, &sheet.data, layout, options)?;
+    let mut sparklines: Vec<Vec<Sparkline>> = vec![Vec::new(); sparkline_columns.len()];
+    
+    for chunk_start in (0..total_rows).step_by(chunk_size) {
+        let chunk_end = std::cmp::min(chunk_start + chunk_size, total_rows);
+        let chunk = &sheet.data[chunk_start..chunk_end];
+        
+        // Pre-process chunk untuk type detection; dengan parallel, konversi dibagi ke thread pool rayon.
+        // Penulisan ke worksheet tetap serial karena xlsxwriter tidak thread-safe.
+        let processed_chunk: Vec<Vec<CellValue>> = if parallel {
+            chunk
+                .par_iter()
+                .map(|record| json_to_excel_row_optimized(record, &headers, options))
+                .collect()
+        } else {
+            chunk
+                .iter()
+                .map(|record| json_to_excel_row_optimized(record, &headers, options))
+                .collect()
+        };
+        
+        // Write chunk ke Excel
+        for (chunk_row_idx, excel_row) in processed_chunk.iter().enumerate() {
+            let row_num = layout.data_row(chunk_start + chunk_row_idx);
+            let mut image_height = 0.0_f64;
+            
+            // Row yang field-nya bukan array angka dilewati; cell target-nya ditulis seperti biasa
+            let mut sparkline_targets = Vec::new();
+            for (group, column) in sparkline_columns.iter().enumerate() {
+                let Some(values) = numeric_array(record_value(&chunk[chunk_row_idx], column.field)) else {
+                    continue;
+                };
+                for (point, value) in values.iter().enumerate() {
+                    worksheet.write_number(row_num, column.helper_col + point as u16, *value, None)?;
+                }
+                sparklines[group].push(Sparkline::new(sheet_name, row_num, column.helper_col, values.len(), layout.col(column.col)));
+                sparkline_targets.push(column.col);
+            }
+            
+            for (col, cell_value) in excel_row.iter().enumerate() {
+                if let Some(columns) = &mut dictionary {
+                    columns[col].add(record_value(&chunk[chunk_row_idx], &headers[col]), cell_value);
+                }
+                if sparkline_targets.contains(&col) {
+                    worksheet.write_blank(row_num, layout.col(col), cell_formats.column(layout.col(col)).plain.as_ref())?;
+                    continue;
+                }
+                if image_columns[col] {
+                    let value = record_value(&chunk[chunk_row_idx], &headers[col]);
+                    if let Some((width, height)) = insert_cell_image(&mut worksheet, row_num, layout.col(col), value) {
+                        image_widths[col] = image_widths[col].max(width);
+                        image_height = image_height.max(height);
+                        continue;
+                    }
+                }
+                match &formulas[col] {
+                    Some(template) => {
+                        let formula = template.replace("{row}", &(row_num + 1).to_string());
+                        let column_formats = cell_formats.column(layout.col(col));
+                        let format = column_formats.number.as_ref().or(column_formats.plain.as_ref());
+                        worksheet.write_formula(row_num, layout.col(col), &formula, format)?;
+                    }
+                    None => write_cell(&mut worksheet, row_num, layout.col(col), cell_value, &cell_formats)?,
+                }
+                if let Some(summary) = &mut summaries[col] {
+                    summary.add(cell_value);
+                }
+            }
+            // Wrapper xlsxwriter tidak mengekspos set_default_row, jadi row_height di-set per row.
+            // Row dengan gambar tetap cukup tinggi untuk gambarnya
+            let row_height = options.row_height.unwrap_or(0.0).max(image_height * POINTS_PER_PIXEL);
+            if row_height > 0.0 {
+                worksheet.set_row(row_num, row_height, None)?;
+            }
+            if let Some(comments_field) = &options.comments_field {
+                write_row_comments(&mut worksheet, row_num, &chunk[chunk_row_idx][comments_field], &header_columns)?;
+            }
+        }
+        
+        // Log progress
+        report_job_progress(chunk_end - chunk_start);
+        debug!("Chunk rows {}..{} written to '{}'", chunk_start, chunk_end, sheet_name);
+        if chunk_end % 10000 == 0 || chunk_end == total_rows {
+            info!("📈 Progress: {} / {} rows processed", chunk_end, total_rows);
+        }
+    }
+
+    for (col, image_width) in image_widths.iter().enumerate() {
+        let image_chars = image_width / PIXELS_PER_CHAR + 1.0;
+        if *image_width > 0.0 && image_chars > widths[col] {
+            widths[col] = image_chars.min(MAX_COLUMN_WIDTH);
+            worksheet.set_column(layout.col(col), layout.col(col), widths[col], None)?;
+        }
+    }
+
+    if let Some(merge_column) = &options.merge_column {
+        merge_repeated_values(&mut worksheet, layout, sheet, &headers, merge_column, &cell_formats, options)?;
+    }
+
+    // Footer summaries tepat di bawah data, di luar range table / auto_filter
+    if options.summaries.is_some() {
+        let row = layout.data_row(total_rows);
+        for (col, summary) in summaries.iter().enumerate() {
+            if let Some(value) = summary.as_ref().and_then(SummaryAccumulator::value) {
+                worksheet.write_number(row, layout.col(col), value, Some(&cell_formats.summary[col]))?;
+            }
+        }
+    }
+
+    if let Some(specs) = &options.conditional_formats {
+        add_conditional_formats(&mut worksheet, layout, &headers, total_rows, specs)?;
+    }
+
+    // Excel Table sudah punya filter sendiri, jadi auto_filter dilewati kalau as_table aktif
+    if options.as_table.unwrap_or(false) && !headers.is_empty() {
+        add_data_table(&mut worksheet, layout, &titles, total_rows, options)?;
+    } else if options.auto_filter.unwrap_or(false) && !headers.is_empty() {
+        let last_row = layout.last_row(total_rows);
+        worksheet.autofilter(layout.header_row, layout.first_col, last_row, layout.last_col(headers.len()))?;
+    }
+
+    // Disembunyikan paling akhir dengan lebar final, supaya set_column di atas tidak menimpa flag hidden.
+    // Nama yang bukan kolom export diabaikan
+    let hidden = RowColOptions::new(true, 0, false);
+    for (col, header) in headers.iter().enumerate() {
+        if options.hidden_columns.iter().flatten().any(|column| column == header) {
+            worksheet.set_column_opt(layout.col(col), layout.col(col), widths[col], None, &hidden)?;
+        }
+    }
+    for column in sparkline_columns.iter().filter(|column| column.points > 0) {
+        let last_col = column.helper_col + column.points as u16 - 1;
+        worksheet.set_column_opt(column.helper_col, last_col, DEFAULT_COLUMN_WIDTH, None, &hidden)?;
+    }
+
+    Ok(WrittenSheet { dictionary, sparklines })
+}
+
+// Nama sheet tambahan include_dictionary
+const DICTIONARY_SHEET_NAME: &str = "Dictionary";
+
+// Satu row per kolom setiap sheet data: sheet, kolom, tipe hasil inferensi, jumlah cell terisi, dan fill rate
+fn write_dictionary_sheet(
+    workbook: &Workbook,
+    entries: &[(String, usize, Vec<ColumnReport>)],
+    options: &ExportOptions,
+) -> anyhow::Result<()> {
+    info!("📝 Creating worksheet: {}", DICTIONARY_SHEET_NAME);
+    let mut worksheet = workbook.add_worksheet(Some(DICTIONARY_SHEET_NAME))?;
+    let header_format = header_format(options.header_style.as_ref())?;
+    for (col, title) in ["Sheet", "Column", "Type", "Filled", "Fill Rate"].iter().enumerate() {
+        worksheet.write_string(0, col as u16, title, Some(&header_format))?;
+        worksheet.set_column(col as u16, col as u16, DEFAULT_COLUMN_WIDTH, None)?;
+    }
+    let mut percent_format = Format::new();
+    percent_format.set_num_format("0.0%");
+    let mut row = 1;
+    for (sheet_name, records, columns) in entries {
+        for column in columns {
+            let filled = records - column.null_count;
+            worksheet.write_string(row, 0, sheet_name, None)?;
+            worksheet.write_string(row, 1, display_header(&column.name, options), None)?;
+            worksheet.write_string(row, 2, column.inferred_type, None)?;
+            worksheet.write_number(row, 3, filled as f64, None)?;
+            if *records > 0 {
+                worksheet.write_number(row, 4, filled as f64 / *records as f64, Some(&percent_format))?;
+            }
+            row += 1;
+        }
+    }
+    Ok(())
+}
+
+// Posisi satu sparkline_columns di sheet: kolom target (index headers) dan helper column tersembunyi
+// setelah blok data, selebar array angka terpanjang
+struct SparklineColumn<'a> {
+    field: &'a str,
+    col: usize,
+    helper_col: u16,
+    points: usize,
+}
+
+fn sparkline_columns<'a>(
+    headers: &[String],
+    data: &[Value],
+    layout: SheetLayout,
+    options: &'a ExportOptions,
+) -> Result<Vec<SparklineColumn<'a>>, ExcelError> {
+    let mut next_col = layout.col(headers.len()) as usize;
+    let mut columns = Vec::new();
+    for spec in options.sparkline_columns.iter().flatten() {
+        let col = headers.iter().position(|header| *header == spec.column).ok_or_else(|| {
+            ExcelError::InvalidOptions(format!("sparkline_columns column '{}' is not one of the exported columns", spec.column))
+        })?;
+        let points = data
+            .iter()
+            .filter_map(|record| numeric_array(record_value(record, &spec.field)))
+            .map(|values| values.len())
+            .max()
+            .unwrap_or(0);
+        if next_col + points > EXCEL_MAX_COLS as usize {
+            return Err(ExcelError::InvalidOptions(format!(
+                "sparkline_columns field '{}' needs {} helper columns, which do not fit after the data (max {} columns)",
+                spec.field, points, EXCEL_MAX_COLS
+            )));
+        }
+        columns.push(SparklineColumn { field: &spec.field, col, helper_col: next_col as u16, points });
+        next_col += points;
+    }
+    Ok(columns)
+}
+
+// Array yang semua element-nya angka; array kosong atau berisi non-angka None
+fn numeric_array(value: &Value) -> Option<Vec<f64>> {
+    match value {
+        Value::Array(items) if !items.is_empty() => items.iter().map(Value::as_f64).collect(),
+        _ => None,
+    }
+}
+
+// Satu sparkline: range helper cell (mis. 'Data'!$E$2:$G$2) dan cell lokasinya (mis. C2)
+#[derive(Debug, Clone, PartialEq)]
+struct Sparkline {
+    range: String,
+    location: String,
+}
+
+impl Sparkline {
+    fn new(sheet_name: &str, row: u32, first_col: u16, points: usize, location_col: u16) -> Self {
+        let last_col = first_col as u32 + points as u32 - 1;
+        Sparkline {
+            range: format!(
+                "'{}'!${}${}:${}${}",
+                sheet_name.replace('\'', "''"),
+                column_letters(first_col as u32),
+                row + 1,
+                column_letters(last_col),
+                row + 1
+            ),
+            location: format!("{}{}", column_letters(location_col as u32), row + 1),
+        }
+    }
+}
+
+// Template formula per kolom (index sesuai headers). Kolom formula harus ikut di-export, dan referensi
+// cell di formula hanya boleh ke kolom di dalam blok data
+fn formula_templates(headers: &[String], layout: SheetLayout, options: &ExportOptions) -> anyhow::Result<Vec<Option<String>>> {
+    let Some(formula_columns) = &options.formula_columns else {
+        return Ok(headers.iter().map(|_| None).collect());
+    };
+    let first_col = layout.first_col as u32;
+    let last_col = layout.last_col(headers.len()) as u32;
+    for (column, template) in formula_columns {
+        if !headers.contains(column) {
+            return Err(ExcelError::InvalidOptions(format!(
+                "formula_columns '{}' is not one of the exported columns",
+                column
+            ))
+            .into());
+        }
+        for letters in formula_column_refs(template) {
+            let referenced = column_index(letters);
+            if !(first_col..=last_col).contains(&referenced) {
+                return Err(ExcelError::InvalidOptions(format!(
+                    "formula_columns '{}' references column {} outside the exported columns {}:{}",
+                    column,
+                    letters.to_ascii_uppercase(),
+                    column_letters(first_col),
+                    column_letters(last_col)
+                ))
+                .into());
+            }
+        }
+    }
+    Ok(headers.iter().map(|header| formula_columns.get(header).cloned()).collect())
+}
+
+// Huruf kolom dari referensi cell A1-style (B5, $C$7, D{row}); isi string "...", nama fungsi dan
+// referensi ke sheet lain (Sheet2!A1) dilewati
+fn formula_column_refs(formula: &str) -> Vec<&str> {
+    let bytes = formula.as_bytes();
+    let mut refs = Vec::new();
+    let mut in_string = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b == b'"' {
+            in_string = !in_string;
+            i += 1;
+            continue;
+        }
+        let starts_token = i == 0 || !(bytes[i - 1].is_ascii_alphanumeric() || matches!(bytes[i - 1], b'_' | b'.'));
+        if in_string || !b.is_ascii_alphabetic() || !starts_token {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && bytes[i].is_ascii_alphabetic() {
+            i += 1;
+        }
+        let mut end = i;
+        if end < bytes.len() && bytes[end] == b'$' {
+            end += 1;
+        }
+        let row_len = if formula[end..].starts_with("{row}") {
+            "{row}".len()
+        } else {
+            bytes[end..].iter().take_while(|b| b.is_ascii_digit()).count()
+        };
+        end += row_len;
+        let followed_by_name = end < bytes.len() && (bytes[end].is_ascii_alphanumeric() || matches!(bytes[end], b'_' | b'('));
+        let other_sheet = formula[..start].trim_end_matches('$').ends_with('!');
+        if row_len > 0 && i - start <= 3 && !followed_by_name {
+            if !other_sheet {
+                refs.push(&formula[start..i]);
+            }
+            i = end;
+        }
+    }
+    refs
+}
+
+// "A" -> 0, "AB" -> 27 (huruf besar / kecil sama)
+fn column_index(letters: &str) -> u32 {
+    letters
+        .bytes()
+        .fold(0, |index, b| index * 26 + (b.to_ascii_uppercase() - b'A') as u32 + 1)
+        - 1
+}
+
+// 0 -> "A", 27 -> "AB"
+fn column_letters(index: u32) -> String {
+    let mut letters = Vec::new();
+    let mut n = index + 1;
+    while n > 0 {
+        letters.push(b'A' + ((n - 1) % 26) as u8);
+        n = (n - 1) / 26;
+    }
+    letters.reverse();
+    String::from_utf8(letters).unwrap_or_default()
+}
+
+// Sisi terpanjang gambar di cell (pixel); gambar lebih besar diperkecil proporsional
+const IMAGE_MAX_PX: f64 = 120.0;
+// Konversi kira-kira pixel ke satuan Excel (font default Calibri 11)
+const POINTS_PER_PIXEL: f64 = 0.75;
+const PIXELS_PER_CHAR: f64 = 7.0;
+
+// Embed gambar di cell kalau value adalah gambar base64 / data URI yang dikenali (PNG, JPEG, GIF, BMP).
+// Return ukuran tampil (pixel); None berarti bukan gambar atau ditolak xlsxwriter, jadi cell ditulis sebagai teks
+fn insert_cell_image(worksheet: &mut Worksheet, row: u32, col: u16, value: &Value) -> Option<(f64, f64)> {
+    let Value::String(s) = value else {
+        return None;
+    };
+    let encoded = match s.strip_prefix("data:") {
+        Some(rest) => {
+            let (media_type, data) = rest.split_once(',')?;
+            if !media_type.starts_with("image/") || !media_type.ends_with(";base64") {
+                return None;
+            }
+            data
+        }
+        None => s.as_str(),
+    };
+    let bytes = base64::engine::general_purpose::STANDARD.decode(encoded.trim()).ok()?;
+    let (width, height) = image_dimensions(&bytes)?;
+    let (width, height) = (width as f64, height as f64);
+    let scale = (IMAGE_MAX_PX / width.max(height)).min(1.0);
+    let image_options = ImageOptions {
+        x_offset: 0,
+        y_offset: 0,
+        x_scale: scale,
+        y_scale: scale,
+    };
+    worksheet.insert_image_buffer_opt(row, col, &bytes, &image_options).ok()?;
+    Some((width * scale, height * scale))
+}
+
+// Lebar dan tinggi (pixel) dari header file PNG, GIF, BMP atau JPEG
+fn image_dimensions(bytes: &[u8]) -> Option<(u32, u32)> {
+    let be_u16 = |at: usize| Some(u16::from_be_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let le_u16 = |at: usize| Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?) as u32);
+    let be_u32 = |at: usize| Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?));
+    let le_i32 = |at: usize| Some(i32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?).unsigned_abs());
+
+    let dimensions = if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        (be_u32(16)?, be_u32(20)?)
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        (le_u16(6)?, le_u16(8)?)
+    } else if bytes.starts_with(b"BM") {
+        (le_i32(18)?, le_i32(22)?)
+    } else if bytes.starts_with(&[0xFF, 0xD8]) {
+        // Cari segment SOFn (C0-CF kecuali C4 DHT, C8 JPG, CC DAC)
+        let mut i = 2;
+        loop {
+            if *bytes.get(i)? != 0xFF {
+                return None;
+            }
+            let marker = *bytes.get(i + 1)?;
+            if (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC) {
+                break (be_u16(i + 7)?, be_u16(i + 5)?);
+            }
+            i += 2 + be_u16(i + 2)? as usize;
+        }
+    } else {
+        return None;
+    };
+    (dimensions.0 > 0 && dimensions.1 > 0).then_some(dimensions)
+}
+
+// Accumulator per kolom (index sesuai headers); None untuk kolom tanpa summary
+fn summary_accumulators(headers: &[String], options: &ExportOptions) -> anyhow::Result<Vec<Option<SummaryAccumulator>>> {
+    let Some(summaries) = &options.summaries else {
+        return Ok(headers.iter().map(|_| None).collect());
+    };
+    if let Some(unknown) = summaries.keys().find(|column| !headers.contains(column)) {
+        return Err(ExcelError::InvalidOptions(format!(
+            "summaries column '{}' is not one of the exported columns",
+            unknown
+        ))
+        .into());
+    }
+    Ok(headers
+        .iter()
+        .map(|header| summaries.get(header).map(|aggregate| SummaryAccumulator::new(*aggregate)))
+        .collect())
+}
+
+// Agregat footer satu kolom. Hanya cell angka yang dihitung; string, bool, tanggal dan kosong dilewati
+struct SummaryAccumulator {
+    aggregate: Aggregate,
+    count: usize,
+    sum: f64,
+    min: f64,
+    max: f64,
+}
+
+impl SummaryAccumulator {
+    fn new(aggregate: Aggregate) -> Self {
+        SummaryAccumulator {
+            aggregate,
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+        }
+    }
+
+    fn add(&mut self, cell: &CellValue) {
+        let value = match cell {
+            CellValue::Integer(i) => *i as f64,
+            CellValue::Float(f) if f.is_finite() => *f,
+            _ => return,
+        };
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    // None kalau tidak ada angka sama sekali (avg/min/max); cell footer dibiarkan kosong
+    fn value(&self) -> Option<f64> {
+        match self.aggregate {
+            Aggregate::Sum => Some(self.sum),
+            Aggregate::Count => Some(self.count as f64),
+            _ if self.count == 0 => None,
+            Aggregate::Avg => Some(self.sum / self.count as f64),
+            Aggregate::Min => Some(self.min),
+            Aggregate::Max => Some(self.max),
+        }
+    }
+}
+
+fn worksheet_protection(protect: &ProtectOptions) -> Protection {
+    let select = protect.allow_select.unwrap_or(true);
+    Protection {
+        no_select_locked_cells: !select,
+        no_select_unlocked_cells: !select,
+        sort: protect.allow_sort.unwrap_or(false),
+        autofilter: protect.allow_autofilter.unwrap_or(false),
+        format_columns: protect.allow_format_columns.unwrap_or(false),
+        format_rows: protect.allow_format_rows.unwrap_or(false),
+        insert_rows: protect.allow_insert_rows.unwrap_or(false),
+        delete_rows: protect.allow_delete_rows.unwrap_or(false),
+        ..Protection::new()
+    }
+}
+
+// Conditional format di range data kolom masing-masing spec; footer summaries tidak ikut
+fn add_conditional_formats(
+    worksheet: &mut Worksheet,
+    layout: SheetLayout,
+    headers: &[String],
+    total_rows: usize,
+    specs: &[CondFormatSpec],
+) -> anyhow::Result<()> {
+    for spec in specs {
+        let Some(col) = headers.iter().position(|header| *header == spec.column) else {
+            return Err(ExcelError::InvalidOptions(format!(
+                "conditional_formats column '{}' is not one of the exported columns",
+                spec.column
+            ))
+            .into());
+        };
+        if total_rows == 0 {
+            continue;
+        }
+        let col = layout.col(col);
+        let (first_row, last_row) = (layout.first_data_row(), layout.last_row(total_rows));
+        worksheet.conditional_format_range(first_row, col, last_row, col, &conditional_format(spec)?)?;
+    }
+    Ok(())
+}
+
+// Terjemahkan spec ke ConditionalFormat xlsxwriter; dipakai juga di validate_sheets
+fn conditional_format(spec: &CondFormatSpec) -> Result<ConditionalFormat, ExcelError> {
+    let color = parse_hex_color(&spec.color)?;
+    let threshold = |value: Option<f64>, name: &str| {
+        value.ok_or_else(|| {
+            ExcelError::InvalidOptions(format!(
+                "conditional_formats column '{}': rule {:?} requires {}",
+                spec.column, spec.rule, name
+            ))
+        })
+    };
+    let mut fill = Format::new();
+    fill.set_bg_color(FormatColor::Custom(color));
+    let conditional_format = match spec.rule {
+        CondRule::GreaterThan => ConditionalFormat::cell_greater_than(threshold(spec.value, "value")?, &fill),
+        CondRule::LessThan => ConditionalFormat::cell_less_than(threshold(spec.value, "value")?, &fill),
+        CondRule::Between => ConditionalFormat::cell_between(
+            threshold(spec.value, "value")?,
+            threshold(spec.max_value, "max_value")?,
+            &fill,
+        ),
+        CondRule::ColorScale => {
+            let min_color = match &spec.min_color {
+                Some(hex) => parse_hex_color(hex)?,
+                None => 0xFFFFFF,
+            };
+            ConditionalFormat::two_color_scale(
+                ConditionalFormatRuleTypes::Minimum,
+                ConditionalFormatRuleTypes::Maximum,
+                0.0,
+                0.0,
+                FormatColor::Custom(min_color),
+                FormatColor::Custom(color),
+            )
+        }
+    };
+    Ok(conditional_format)
+}
+
+// Merge vertikal untuk run value yang sama (minimal 2 baris) di satu kolom. merge_range hanya bisa
+// menulis string, jadi cell pertama ditulis ulang dengan tipe aslinya. Null tidak di-merge.
+fn merge_repeated_values(
+    worksheet: &mut Worksheet,
+    layout: SheetLayout,
+    sheet: &SheetSpec,
+    headers: &[String],
+    merge_column: &str,
+    formats: &CellFormats,
+    options: &ExportOptions,
+) -> anyhow::Result<()> {
+    if options.as_table.unwrap_or(false) {
+        return Err(ExcelError::InvalidOptions(
+            "merge_column cannot be combined with as_table: Excel tables cannot contain merged cells".to_string(),
+        )
+        .into());
+    }
+    let Some(col) = headers.iter().position(|header| header == merge_column) else {
+        return Err(ExcelError::InvalidOptions(format!(
+            "merge_column '{}' is not one of the exported columns",
+            merge_column
+        ))
+        .into());
+    };
+    let header = std::slice::from_ref(&headers[col]);
+    let col = layout.col(col);
+
+    let mut run_start = 0;
+    while run_start < sheet.data.len() {
+        let value = &sheet.data[run_start][merge_column];
+        let run_len = sheet.data[run_start..]
+            .iter()
+            .take_while(|record| &record[merge_column] == value)
+            .count();
+        if run_len > 1 && !value.is_null() {
+            let first_row = layout.data_row(run_start);
+            let last_row = layout.data_row(run_start + run_len - 1);
+            worksheet.merge_range(first_row, col, last_row, col, "", formats.column(col).plain.as_ref())?;
+            let cell = json_to_excel_row_optimized(&sheet.data[run_start], header, options);
+            write_cell(worksheet, first_row, col, &cell[0], formats)?;
+        }
+        run_start += run_len;
+    }
+    Ok(())
+}
+
+// Comment per cell dari object {"kolom": "teks"}; kolom yang tidak dikenal atau value bukan string dilewati
+fn write_row_comments(
+    worksheet: &mut Worksheet,
+    row: u32,
+    comments: &Value,
+    header_columns: &HashMap<&str, u16>,
+) -> anyhow::Result<()> {
+    let Value::Object(comments) = comments else {
+        return Ok(());
+    };
+    for (header, text) in comments {
+        if let (Some(col), Value::String(text)) = (header_columns.get(header.as_str()), text) {
+            worksheet.write_comment(row, *col, text)?;
+        }
+    }
+    Ok(())
+}
+
+// Excel Table di atas header + data. Kalau header_style diisi, style itu tetap dipakai di header table;
+// kalau tidak, header mengikuti style bawaan table.
+fn add_data_table(
+    worksheet: &mut Worksheet,
+    layout: SheetLayout,
+    titles: &[String],
+    total_rows: usize,
+    options: &ExportOptions,
+) -> anyhow::Result<()> {
+    let header_format = match &options.header_style {
+        Some(style) => Some(header_format(Some(style))?),
+        None => None,
+    };
+    let columns = titles
+        .iter()
+        .map(|title| TableColumn {
+            header: Some(title.clone()),
+            header_format: header_format.clone(),
+            ..Default::default()
+        })
+        .collect();
+    let table_options = TableOptions {
+        columns: Some(columns),
+        ..Default::default()
+    };
+    // Table minimal butuh satu baris data, jadi data kosong tetap dapat satu baris kosong
+    let last_row = layout.last_row(std::cmp::max(total_rows, 1));
+    let last_col = layout.last_col(titles.len());
+    worksheet.add_table(layout.header_row, layout.first_col, last_row, last_col, Some(table_options))?;
+    Ok(())
+}
+
+// Format header: default bold, background abu-abu, border tipis; bisa diubah lewat header_style
+fn header_format(style: Option<&HeaderStyle>) -> anyhow::Result<Format> {
+    let mut format = Format::new();
+    let style = style.cloned().unwrap_or_default();
+
+    if style.bold.unwrap_or(true) {
+        format.set_bold();
+    }
+    let bg_color = match &style.bg_color {
+        Some(hex) => parse_hex_color(hex)?,
+        None => 0xE0E0E0,
+    };
+    format.set_bg_color(FormatColor::Custom(bg_color));
+    if let Some(hex) = &style.font_color {
+        format.set_font_color(FormatColor::Custom(parse_hex_color(hex)?));
+    }
+    if style.border.unwrap_or(true) {
+        format.set_border(FormatBorder::Thin);
+    }
+    Ok(format)
+}
+
+// Parse warna hex "FF0000" / "#FF0000" ke RGB
+fn parse_hex_color(hex: &str) -> Result<u32, ExcelError> {
+    let digits = hex.trim().trim_start_matches('#');
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(ExcelError::InvalidOptions(format!(
+            "Invalid color '{}': expected 6 hex digits like \"FF0000\"",
+            hex
+        )));
+    }
+    u32::from_str_radix(digits, 16)
+        .map_err(|e| ExcelError::InvalidOptions(format!("Invalid color '{}': {}", hex, e)))
+}
+
+// Format yang dipakai saat menulis cell data, dibuat sekali per worksheet
+struct CellFormats {
+    // Format per kolom (index sesuai headers), karena column_formats / column_fonts bisa berbeda per kolom
+    columns: Vec<ColumnFormats>,
+    // Untuk kolom di luar headers
+    default: ColumnFormats,
+    // Potong string yang melebihi batas Excel alih-alih error
+    truncate_long_strings: bool,
+    // Format bold footer summaries per kolom (num_format dan font kolom ikut dipakai)
+    summary: Vec<Format>,
+    // Kolom worksheet dari header pertama (options.start_col)
+    first_col: u16,
+}
+
+// Format cell data satu kolom untuk setiap tipe cell
+struct ColumnFormats {
+    // Format untuk cell tanpa num_format (string, bool, blank); None kalau tidak ada style
+    plain: Option<Format>,
+    // Number format kolom (column_formats / round_decimals)
+    number: Option<Format>,
+    // Tanpa num_format Excel akan menampilkan tanggal sebagai serial number
+    date: Format,
+    datetime: Format,
+    // Format hyperlink; Some kalau detect_urls aktif
+    url: Option<Format>,
+    // Format angka dengan pemisah ribuan untuk kolom tanpa number format, index = jumlah desimal
+    // (0..=MAX_GROUPED_DECIMALS); kosong kalau locale tidak di-set
+    grouped: Vec<Format>,
+}
+
+impl CellFormats {
+    fn new(headers: &[String], options: &ExportOptions) -> Self {
+        // Semua format data lewat FormatCache supaya border dan font ikut ke semua tipe cell
+        // dan style yang sama tidak dibuat dua kali
+        let mut cache = FormatCache::default();
+        // column_formats didahulukan; kolom dengan round_decimals dapat format desimal yang sama
+        let grouped = options.locale.is_some();
+        let num_formats: Vec<Option<String>> = headers
+            .iter()
+            .map(|header| {
+                options
+                    .column_formats
+                    .as_ref()
+                    .and_then(|formats| formats.get(header).cloned())
+                    .or_else(|| options.round_decimals.as_ref()?.get(header).copied().map(|decimals| decimals_num_format(decimals, grouped)))
+            })
+            .collect();
+        let base_styles: Vec<StyleKey> = headers
+            .iter()
+            .map(|header| column_style(options, header))
+            .collect();
+        let columns = base_styles
+            .iter()
+            .zip(&num_formats)
+            .map(|(base, num_format)| ColumnFormats::new(&mut cache, base, num_format.as_deref(), options))
+            .collect();
+        let default = ColumnFormats::new(&mut cache, &column_style(options, ""), None, options);
+        let summary = if options.summaries.is_some() {
+            base_styles
+                .iter()
+                .zip(&num_formats)
+                .map(|(base, num_format)| {
+                    cache.get(StyleKey { num_format: num_format.clone(), bold: true, ..base.clone() })
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        CellFormats {
+            columns,
+            default,
+            truncate_long_strings: options.truncate_long_strings.unwrap_or(false),
+            summary,
+            first_col: options.start_col.unwrap_or(0),
+        }
+    }
+
+    // Format untuk kolom worksheet col
+    fn column(&self, col: u16) -> &ColumnFormats {
+        col.checked_sub(self.first_col)
+            .and_then(|index| self.columns.get(index as usize))
+            .unwrap_or(&self.default)
+    }
+}
+
+impl ColumnFormats {
+    fn new(cache: &mut FormatCache, base: &StyleKey, num_format: Option<&str>, options: &ExportOptions) -> Self {
+        let style = |num_format: &str| StyleKey { num_format: Some(num_format.to_string()), ..base.clone() };
+        let grouped = options.locale.is_some();
+        ColumnFormats {
+            plain: (*base != StyleKey::default()).then(|| cache.get(base.clone())),
+            number: num_format.map(|num_format| cache.get(style(num_format))),
+            date: cache.get(style(options.date_format.as_deref().unwrap_or("yyyy-mm-dd"))),
+            datetime: cache.get(style(options.datetime_format.as_deref().unwrap_or("yyyy-mm-dd hh:mm:ss"))),
+            url: options
+                .detect_urls
+                .unwrap_or(false)
+                .then(|| cache.get(StyleKey { hyperlink: true, ..base.clone() })),
+            grouped: if grouped {
+                (0..=MAX_GROUPED_DECIMALS)
+                    .map(|decimals| cache.get(style(&decimals_num_format(decimals as u32, true))))
+                    .collect()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+// Style dasar cell data satu kolom (tanpa num_format): border, column_fonts, dan wrap_columns
+fn column_style(options: &ExportOptions, header: &str) -> StyleKey {
+    let font = options.column_fonts.as_ref().and_then(|fonts| fonts.get(header));
+    StyleKey {
+        border: options.cell_borders.unwrap_or(false),
+        wrap: options.wrap_columns.iter().flatten().any(|column| column == header),
+        align: options.column_alignment.as_ref().and_then(|alignment| alignment.get(header)).copied(),
+        bold: font.and_then(|font| font.bold).unwrap_or(false),
+        italic: font.and_then(|font| font.italic).unwrap_or(false),
+        font_name: font.and_then(|font| font.name.clone()),
+        font_size: font.and_then(|font| font.size).map(f64::to_bits),
+        ..StyleKey::default()
+    }
+}
+
+// Deskripsi style cell data; satu Format per kombinasi yang berbeda
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+struct StyleKey {
+    num_format: Option<String>,
+    border: bool,
+    hyperlink: bool,
+    bold: bool,
+    italic: bool,
+    wrap: bool,
+    align: Option<Alignment>,
+    font_name: Option<String>,
+    // f64::to_bits supaya StyleKey tetap bisa di-hash
+    font_size: Option<u64>,
+}
+
+// Cache Format per StyleKey, supaya write loop hanya memakai Format yang sudah dibuat
+#[derive(Default)]
+struct FormatCache {
+    formats: HashMap<StyleKey, Format>,
+}
+
+impl FormatCache {
+    fn get(&mut self, key: StyleKey) -> Format {
+        self.formats
+            .entry(key)
+            .or_insert_with_key(|key| {
+                let mut format = Format::new();
+                if let Some(num_format) = &key.num_format {
+                    format.set_num_format(num_format);
+                }
+                if key.border {
+                    format.set_border(FormatBorder::Thin);
+                }
+                if key.hyperlink {
+                    format.set_underline(FormatUnderline::Single).set_font_color(FormatColor::Blue);
+                }
+                if key.bold {
+                    format.set_bold();
+                }
+                if key.italic {
+                    format.set_italic();
+                }
+                if key.wrap {
+                    format.set_text_wrap();
+                }
+                if let Some(align) = key.align {
+                    format.set_align(match align {
+                        Alignment::Left => FormatAlignment::Left,
+                        Alignment::Center => FormatAlignment::Center,
+                        Alignment::Right => FormatAlignment::Right,
+                    });
+                }
+                if let Some(font_name) = &key.font_name {
+                    format.set_font_name(font_name);
+                }
+                if let Some(font_size) = key.font_size {
+                    format.set_font_size(f64::from_bits(font_size));
+                }
+                format
+            })
+            .clone()
+    }
+}
+
+// Tulis satu cell sesuai tipenya
+fn write_cell(
+    worksheet: &mut Worksheet,
+    row: u32,
+    col: u16,
+    cell: &CellValue,
+    formats: &CellFormats,
+) -> anyhow::Result<()> {
+    let truncate_long_strings = formats.truncate_long_strings;
+    let formats = formats.column(col);
+    let number_format = formats.number.as_ref();
+    let plain_format = formats.plain.as_ref();
+    match cell {
+        CellValue::Empty => {
+            worksheet.write_blank(row, col, plain_format)?;
+        },
+        CellValue::String(s) => {
+            let s = fit_cell_string(s, row, col, truncate_long_strings)?;
+            // URL yang ditolak xlsxwriter (mis. terlalu panjang) tetap ditulis sebagai teks biasa
+            let written_as_url = match &formats.url {
+                Some(url_format) if is_url(&s) => worksheet.write_url(row, col, &s, Some(url_format)).is_ok(),
+                _ => false,
+            };
+            if !written_as_url {
+                worksheet.write_string(row, col, &s, plain_format)?;
+            }
+        },
+        CellValue::Integer(i) => {
+            let format = number_format.or(formats.grouped.first()).or(plain_format);
+            worksheet.write_number(row, col, *i as f64, format)?;
+        },
+        CellValue::Float(f) => {
+            let format = number_format
+                .or_else(|| formats.grouped.get(float_scale(*f)))
+                .or(plain_format);
+            worksheet.write_number(row, col, *f, format)?;
+        },
+        CellValue::Bool(b) => {
+            worksheet.write_boolean(row, col, *b, plain_format)?;
+        },
+        CellValue::Date(d) => {
+            let datetime = excel_datetime(&d.and_time(chrono::NaiveTime::MIN));
+            worksheet.write_datetime(row, col, &datetime, Some(number_format.unwrap_or(&formats.date)))?;
+        },
+        CellValue::DateTime(dt) => {
+            let format = number_format.unwrap_or(&formats.datetime);
+            worksheet.write_datetime(row, col, &excel_datetime(dt), Some(format))?;
+        },
+    }
+    Ok(())
+}
+
+// Batas ukuran worksheet Excel
+const EXCEL_MAX_ROWS: u32 = 1_048_576;
+const EXCEL_MAX_COLS: u16 = 16_384;
+
+// Batas panjang string per cell di Excel
+const EXCEL_MAX_STRING_LEN: usize = 32767;
+const TRUNCATED_MARKER: &str = "…[truncated]";
+
+// String yang terlalu panjang dipotong dengan marker, atau error kalau truncate tidak diaktifkan
+fn fit_cell_string(s: &str, row: u32, col: u16, truncate: bool) -> Result<std::borrow::Cow<'_, str>, ExcelError> {
+    let len = s.chars().count();
+    if len <= EXCEL_MAX_STRING_LEN {
+        return Ok(std::borrow::Cow::Borrowed(s));
+    }
+    if !truncate {
+        return Err(ExcelError::InvalidRecord(format!(
+            "Cell at row {}, column {} has {} characters; Excel allows at most {}. Set truncate_long_strings to true to truncate it",
+            row + 1, col + 1, len, EXCEL_MAX_STRING_LEN
+        )));
+    }
+    let keep = EXCEL_MAX_STRING_LEN - TRUNCATED_MARKER.chars().count();
+    let mut truncated: String = s.chars().take(keep).collect();
+    truncated.push_str(TRUNCATED_MARKER);
+    Ok(std::borrow::Cow::Owned(truncated))
+}
+
+// URL yang dijadikan hyperlink: http://, https://, mailto: tanpa spasi
+fn is_url(s: &str) -> bool {
+    ["http://", "https://", "mailto:"].iter().any(|scheme| {
+        s.len() > scheme.len() && s.get(..scheme.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(scheme))
+    }) && !s.chars().any(char::is_whitespace)
+}
+
+// Auto-detect headers dari gabungan key semua record (record heterogen tetap lengkap).
+// preserve_order = true: urutan sesuai kemunculan key di JSON; false: urut alfabet.
+fn auto_detect_headers(data: &[Value], preserve_order: bool) -> Vec<String> {
+    let mut seen = std::collections::HashSet::new();
+    let mut headers = Vec::new();
+    for record in data {
+        if let Value::Object(map) = record {
+            for key in map.keys() {
+                if seen.insert(key.as_str()) {
+                    headers.push(key.clone());
+                }
+            }
+        }
+    }
+
+    if headers.is_empty() {
+        return vec!["data".to_string()]; // Fallback
+    }
+    if !preserve_order {
+        headers.sort(); // Sort untuk konsistensi
+    }
+    headers
+}
+
+const DEFAULT_COLUMN_WIDTH: f64 = 15.0;
+// Batas lebar kolom di Excel
+const MAX_COLUMN_WIDTH: f64 = 255.0;
+const AUTOFIT_SAMPLE_ROWS: usize = 1000;
+const AUTOFIT_MAX_WIDTH: usize = 80;
+
+// Lebar kolom: autofit dari AUTOFIT_SAMPLE_ROWS record pertama, atau default_column_width / 15
+fn column_widths(headers: &[String], titles: &[String], records: &[Value], options: &ExportOptions) -> Vec<f64> {
+    if options.autofit.unwrap_or(false) {
+        let sample_rows: Vec<Vec<CellValue>> = records
+            .iter()
+            .take(AUTOFIT_SAMPLE_ROWS)
+            .map(|record| json_to_excel_row_optimized(record, headers, options))
+            .collect();
+        let titles: Vec<&str> = titles.iter().map(String::as_str).collect();
+        autofit_column_widths(&titles, &sample_rows)
+    } else {
+        vec![options.default_column_width.unwrap_or(DEFAULT_COLUMN_WIDTH); headers.len()]
+    }
+}
+
+// Lebar kolom = lebar teks terpanjang (header + sample rows) + 2, maksimal 80
+fn autofit_column_widths(headers: &[&str], sample_rows: &[Vec<CellValue>]) -> Vec<f64> {
+    headers
+        .iter()
+        .enumerate()
+        .map(|(col, header)| {
+            let max_len = sample_rows
+                .iter()
+                .filter_map(|row| row.get(col))
+                .map(cell_display_len)
+                .fold(text_display_width(header), usize::max);
+            std::cmp::min(max_len + 2, AUTOFIT_MAX_WIDTH) as f64
+        })
+        .collect()
+}
+
+// Representasi teks cell untuk output berbasis teks (CSV)
+fn cell_to_text(cell: &CellValue) -> String {
+    match cell {
+        CellValue::Empty => String::new(),
+        CellValue::String(s) => s.clone(),
+        CellValue::Integer(i) => i.to_string(),
+        CellValue::Float(f) => f.to_string(),
+        CellValue::Bool(b) => b.to_string(),
+        CellValue::Date(d) => d.format("%Y-%m-%d").to_string(),
+        CellValue::DateTime(dt) => dt.format("%Y-%m-%d %H:%M:%S").to_string(),
+    }
+}
+
+// Perkiraan panjang teks cell saat ditampilkan di Excel
+fn cell_display_len(cell: &CellValue) -> usize {
+    match cell {
+        CellValue::Empty => 0,
+        CellValue::String(s) => text_display_width(s),
+        CellValue::Integer(i) => i.to_string().len(),
+        CellValue::Float(f) => f.to_string().len(),
+        CellValue::Bool(b) => if *b { 4 } else { 5 }, // TRUE / FALSE
+        CellValue::Date(_) => 10, // yyyy-mm-dd
+        CellValue::DateTime(_) => 19, // yyyy-mm-dd hh:mm:ss
+    }
+}
+
+// Lebar teks dalam karakter Excel: dihitung per karakter (bukan byte), karakter CJK /
+// fullwidth dihitung 2 karena tampil kira-kira dua kali lebar huruf latin
+fn text_display_width(s: &str) -> usize {
+    s.chars()
+        .map(|c| {
+            let wide = matches!(c as u32,
+                0x1100..=0x115F // Hangul Jamo
+                | 0x2E80..=0x303E // CJK radicals, punctuation
+                | 0x3041..=0x33FF // Hiragana, Katakana, CJK compatibility
+                | 0x3400..=0x4DBF // CJK extension A
+                | 0x4E00..=0x9FFF // CJK unified ideographs
+                | 0xA960..=0xA97F // Hangul Jamo extended-A
+                | 0xAC00..=0xD7A3 // Hangul syllables
+                | 0xF900..=0xFAFF // CJK compatibility ideographs
+                | 0xFE30..=0xFE4F // CJK compatibility forms
+                | 0xFF00..=0xFF60 // Fullwidth forms
+                | 0xFFE0..=0xFFE6
+                | 0x20000..=0x3FFFD // CJK extension B dst.
+            );
+            if wide { 2 } else { 1 }
+        })
+        .sum()
+}
+
+// Value satu kolom; record scalar (layout "data") dipakai apa adanya
+fn record_value<'a>(record: &'a Value, header: &str) -> &'a Value {
+    match record {
+        Value::Object(_) => &record[header],
+        scalar => scalar,
+    }
+}
+
+// Optimized: Convert JSON record ke Excel row dengan type detection
+fn json_to_excel_row_optimized(record: &Value, headers: &[String], options: &ExportOptions) -> Vec<CellValue> {
+    headers.iter().map(|header| {
+        let value = record_value(record, header);
+        // Tipe dari column_types didahulukan, lalu numeric_columns (string yang tidak bisa di-parse tetap teks),
+        // selain itu pakai type inference
+        let cell = match (options.column_types.as_ref().and_then(|types| types.get(header)), value) {
+            (Some(column_type), _) => typed_value_to_cell(value, *column_type, options),
+            (None, Value::String(s)) if options.numeric_columns.iter().flatten().any(|column| column == header) => {
+                parse_number(s).unwrap_or_else(|| CellValue::String(s.clone()))
+            }
+            (None, _) => json_value_to_cell(value, options),
+        };
+        let cell = match (cell, options.round_decimals.as_ref().and_then(|decimals| decimals.get(header))) {
+            (CellValue::Float(f), Some(decimals)) => CellValue::Float(round_to_decimals(f, *decimals)),
+            (cell, _) => cell,
+        };
+        // bool_format: tulis boolean sebagai label teks, bukan boolean native Excel
+        // null_value: key yang tidak ada dan null eksplisit sama-sama dianggap kosong (record[header] == Null)
+        match (cell, &options.bool_format, &options.null_value) {
+            (CellValue::Float(f), _, _) => float_to_cell(f, options),
+            (CellValue::Bool(b), Some((true_label, false_label)), _) => {
+                CellValue::String(if b { true_label } else { false_label }.clone())
+            }
+            (CellValue::Empty, _, Some(placeholder)) => CellValue::String(placeholder.clone()),
+            (cell, _, _) => cell,
+        }
+    }).collect()
+}
+
+// non_finite_as: NaN/Infinity tidak valid di xlsx, jadi ditulis sebagai teks atau cell kosong
+// (cell kosong ini tidak diganti null_value karena value-nya bukan null)
+fn float_to_cell(f: f64, options: &ExportOptions) -> CellValue {
+    if f.is_finite() {
+        return CellValue::Float(f);
+    }
+    options.non_finite_as.clone().map_or(CellValue::Empty, CellValue::String)
+}
+
+// Di atas 15 desimal f64 sudah tidak lebih presisi, jadi value dibiarkan
+fn round_to_decimals(value: f64, decimals: u32) -> f64 {
+    if decimals > 15 {
+        return value;
+    }
+    let factor = 10f64.powi(decimals as i32);
+    let rounded = (value * factor).round() / factor;
+    if rounded.is_finite() { rounded } else { value }
+}
+
+// Number format yang cocok dengan round_decimals, mis. 2 -> "0.00" (grouped: "#,##0.00")
+fn decimals_num_format(decimals: u32, grouped: bool) -> String {
+    let integer_part = if grouped { "#,##0" } else { "0" };
+    if decimals == 0 {
+        return integer_part.to_string();
+    }
+    format!("{}.{}", integer_part, "0".repeat(decimals.min(15) as usize))
+}
+
+// Kalau locale di-set, angka tanpa number format ditulis dengan "#,##0" plus desimal sebanyak value aslinya.
+// Di format code Excel "," selalu pemisah ribuan dan "." desimal; simbol yang tampil (1.234,56 vs 1,234.56)
+// mengikuti regional setting di komputer pembaca
+const MAX_GROUPED_DECIMALS: usize = 15;
+
+// Locale yang diterima options.locale; semuanya memakai pemisah ribuan
+const SUPPORTED_LOCALES: &[&str] = &[
+    "id-ID", "en-US", "en-GB", "ms-MY", "de-DE", "fr-FR", "nl-NL", "es-ES", "it-IT", "pt-BR", "ja-JP", "zh-CN",
+];
+
+// Jumlah desimal representasi terpendek f (1234.5 -> 1, 0.125 -> 3), maksimal MAX_GROUPED_DECIMALS
+fn float_scale(f: f64) -> usize {
+    let repr = f.to_string();
+    repr.split_once('.').map_or(0, |(_, fraction)| fraction.len()).min(MAX_GROUPED_DECIMALS)
+}
+
+// Type inference default untuk satu JSON value
+fn json_value_to_cell(value: &Value, options: &ExportOptions) -> CellValue {
+    match value {
+        Value::Null => CellValue::Empty,
+        Value::Bool(b) => CellValue::Bool(*b),
+        Value::Number(n) => long_integer_as_text(number_to_cell(n), options),
+        Value::String(s) if options.coerce_numeric_strings.unwrap_or(false) => {
+            parse_numeric_string(s).map_or_else(|| CellValue::String(s.clone()), |cell| long_integer_as_text(cell, options))
+        }
+        Value::String(s) => CellValue::String(s.clone()),
+        Value::Array(_) | Value::Object(_) if options.complex_value_mode == Some(ComplexMode::Json) => {
+            CellValue::String(value.to_string())
+        }
+        Value::Array(items) => CellValue::String(join_array(items, options.array_join.as_deref().unwrap_or(", "))),
+        Value::Object(_) => CellValue::String("[Object]".to_string()),
+    }
+}
+
+// Paksa value ke tipe kolom; kalau tidak bisa dikonversi, fallback ke type inference
+fn typed_value_to_cell(value: &Value, column_type: ColumnType, options: &ExportOptions) -> CellValue {
+    let inferred = || json_value_to_cell(value, options);
+    match (column_type, value) {
+        (_, Value::Null) => CellValue::Empty,
+        (ColumnType::Text, Value::String(s)) => CellValue::String(s.clone()),
+        (ColumnType::Text, Value::Number(n)) => CellValue::String(n.to_string()),
+        (ColumnType::Text, Value::Bool(b)) => CellValue::String(b.to_string()),
+        (ColumnType::Number, Value::Number(n)) => number_to_cell(n),
+        (ColumnType::Number, Value::String(s)) => parse_number(s).unwrap_or_else(inferred),
+        (ColumnType::Number, Value::Bool(b)) => CellValue::Integer(*b as i64),
+        (ColumnType::Bool, Value::String(s)) => parse_bool(s).map(CellValue::Bool).unwrap_or_else(inferred),
+        (ColumnType::Bool, Value::Number(n)) => n.as_f64().map(|f| CellValue::Bool(f != 0.0)).unwrap_or_else(inferred),
+        (ColumnType::Date, Value::String(s)) => parse_date(s).unwrap_or_else(inferred),
+        _ => inferred(),
+    }
+}
+
+fn parse_number(s: &str) -> Option<CellValue> {
+    let s = s.trim();
+    if let Ok(i) = s.parse::<i64>() {
+        return Some(integer_to_cell(i));
+    }
+    s.parse::<f64>().ok().filter(|f| f.is_finite()).map(CellValue::Float)
+}
+
+// Lebih ketat dari parse_number karena dipakai tanpa column_types: hanya -?(0|[1-9][0-9]*)(.[0-9]+)?
+// tanpa spasi / exponent, jadi kode seperti "007" (leading zero) atau "123abc" tetap teks
+fn parse_numeric_string(s: &str) -> Option<CellValue> {
+    let unsigned = s.strip_prefix('-').unwrap_or(s);
+    let (integer_part, fraction) = match unsigned.split_once('.') {
+        Some((integer_part, fraction)) => (integer_part, Some(fraction)),
+        None => (unsigned, None),
+    };
+    let all_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    if !all_digits(integer_part) || (integer_part.len() > 1 && integer_part.starts_with('0')) {
+        return None;
+    }
+    match fraction {
+        None => s.parse::<i64>().ok().map(integer_to_cell),
+        Some(fraction) if all_digits(fraction) => s.parse::<f64>().ok().filter(|f| f.is_finite()).map(CellValue::Float),
+        Some(_) => None,
+    }
+}
+
+fn parse_bool(s: &str) -> Option<bool> {
+    match s.trim().to_lowercase().as_str() {
+        "true" | "yes" | "1" => Some(true),
+        "false" | "no" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+// Format yang dikenali: 2024-03-01, 2024-03-01 10:30:00, 2024-03-01T10:30:00, RFC 3339
+fn parse_date(s: &str) -> Option<CellValue> {
+    let s = s.trim();
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        return Some(CellValue::Date(date));
+    }
+    if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(s) {
+        return Some(CellValue::DateTime(datetime.naive_local()));
+    }
+    ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+        .iter()
+        .find_map(|fmt| chrono::NaiveDateTime::parse_from_str(s, fmt).ok())
+        .map(CellValue::DateTime)
+}
+
+// Array of scalars di-join dengan separator; kalau ada nested object/array, simpan sebagai JSON compact
+fn join_array(items: &[Value], separator: &str) -> String {
+    if items.iter().any(|v| v.is_array() || v.is_object()) {
+        return serde_json::to_string(items).unwrap_or_default();
+    }
+
+    items
+        .iter()
+        .map(|v| match v {
+            Value::String(s) => s.clone(),
+            Value::Null => String::new(),
+            other => other.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(separator)
+}
+
+// Integer tetap integer, float tetap float; u64 yang tidak muat di i64 jadi string
+fn number_to_cell(n: &serde_json::Number) -> CellValue {
+    if let Some(i) = n.as_i64() {
+        integer_to_cell(i)
+    } else if n.is_f64() {
+        CellValue::Float(n.as_f64().unwrap_or_default())
+    } else {
+        CellValue::String(n.to_string())
+    }
+}
+
+// Integer terbesar yang masih presisi sebagai f64 (2^53 - 1)
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+// Excel menyimpan angka sebagai f64; integer di luar ±(2^53 - 1) (mis. Snowflake ID) ditulis sebagai teks
+// supaya digit tidak berubah
+fn integer_to_cell(i: i64) -> CellValue {
+    if i.unsigned_abs() > MAX_SAFE_INTEGER {
+        CellValue::String(i.to_string())
+    } else {
+        CellValue::Integer(i)
+    }
+}
+
+// Excel hanya menyimpan 15 digit signifikan: integer 16+ digit yang masih di bawah 2^53 pun tampil dengan
+// digit terakhir jadi 0. Hanya untuk type inference; column_types dan numeric_columns tidak terpengaruh
+const EXCEL_MAX_EXACT_INTEGER: u64 = 999_999_999_999_999;
+
+fn long_integer_as_text(cell: CellValue, options: &ExportOptions) -> CellValue {
+    match cell {
+        CellValue::Integer(i) if i.unsigned_abs() > EXCEL_MAX_EXACT_INTEGER && options.long_numbers_as_text.unwrap_or(true) => {
+            CellValue::String(i.to_string())
+        }
+        cell => cell,
+    }
+}
+
+// Enum untuk optimized cell values
+#[derive(Debug)]
+#[cfg_attr(test, derive(PartialEq))]
+enum CellValue {
+    Empty,
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Bool(bool),
+    Date(chrono::NaiveDate),
+    DateTime(chrono::NaiveDateTime),
+}
+
+// Tipe kolom yang bisa dipaksa lewat options.column_types
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ColumnType {
+    Text,
+    Number,
+    Bool,
+    Date,
+}
+
+// Cara menulis nested object (dan array) di cell
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+enum ComplexMode {
+    // Object jadi "[Object]", array of scalars di-join array_join
+    #[default]
+    Placeholder,
+    // Object dan array ditulis sebagai JSON compact
+    Json,
+    // Object dipecah jadi kolom "parent.child" sebelum header detection; array seperti placeholder
+    Flatten,
+}
+
+// Perataan horizontal cell data (column_alignment)
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+// Perilaku kalau data satu sheet melebihi batas row Excel
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+enum RowOverflow {
+    // Tolak dengan TOO_MANY_ROWS sebelum workbook ditulis
+    #[default]
+    Error,
+    // Sisa data dilanjutkan di sheet baru dengan header dan opsi yang sama
+    Split,
+}
+
+// Agregat untuk footer row options.summaries
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum Aggregate {
+    Sum,
+    Avg,
+    Count,
+    Min,
+    Max,
+}
+
+// Health check endpoint
+async fn health_handler() -> Result<impl warp::Reply, Infallible> {
+    let response = HealthResponse {
+        status: "healthy".to_string(),
+        service: "excel-service".to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+    };
+    
+    Ok(warp::reply::json(&response))
+}
+
+// Deep health check: coba tulis dan close workbook kecil di temp dir (mendeteksi /tmp read-only, dsb.)
+async fn deep_health_handler() -> Result<impl warp::Reply, Infallible> {
+    let result = spawn_blocking_with_request_id(write_probe_workbook)
+        .await
+        .map_err(|e| anyhow::anyhow!("Health check task failed: {}", e))
+        .and_then(|result| result);
+    
+    let (status, reason, code) = match result {
+        Ok(()) => ("ok", None, warp::http::StatusCode::OK),
+        Err(e) => {
+            error!("❌ Deep health check failed: {}", e);
+            ("degraded", Some(e.to_string()), warp::http::StatusCode::SERVICE_UNAVAILABLE)
+        }
+    };
+    let response = DeepHealthResponse {
+        status,
+        reason,
+        temp_dir: temp_dir_path().display().to_string(),
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&response), code))
+}
+
+fn write_probe_workbook() -> anyhow::Result<()> {
+    let temp_file = TempFile::new("xlsx")?;
+    let temp_dir = temp_dir_path();
+    let workbook = Workbook::new_with_options(temp_file.path_str()?, false, temp_dir.to_str(), false)?;
+    workbook.add_worksheet(None)?.write_string(0, 0, "ok", None)?;
+    workbook.close()?;
+    let size = std::fs::metadata(&temp_file.path)?.len();
+    if size == 0 {
+        anyhow::bail!("Probe workbook is empty");
+    }
+    Ok(())
+}
+
+// CSV to Excel handler: CSV body + options opsional di header X-Options (JSON, sama dengan options /generate-excel)
+async fn csv_to_excel_handler(options: Option<String>, body: bytes::Bytes) -> Result<impl warp::Reply, warp::Rejection> {
+    let options = parse_csv_options(options.as_deref()).map_err(warp::reject::custom)?;
+    let output_format = options.format.unwrap_or_default();
+    let filename = sanitize_filename(&options.filename, output_format.extension()).map_err(warp::reject::custom)?;
+    let csv_data = String::from_utf8_lossy(&body).to_string();
+    
+    let slot = acquire_generation_slot().await.map_err(warp::reject::custom)?;
+    let start_time = std::time::Instant::now();
+    
+    info!("🦀 Starting CSV to Excel conversion");
+    
+    match GENERATION_SLOT.scope(slot, convert_csv_to_excel(csv_data, options)).await {
+        Ok((excel_data, records_processed)) => {
+            let duration = start_time.elapsed();
+            info!("✅ CSV to Excel conversion completed in {:?}", duration);
+            METRICS.record_success(duration, records_processed);
+            
+            Ok(file_reply(excel_data, output_format.content_type(), &filename))
+        }
+        Err(e) => {
+            error!("❌ CSV to Excel conversion failed: {}", e);
+            METRICS.record_failure(&e);
+            Err(to_rejection(e))
+        }
+    }
+}
+
+// Options dari header X-Options dengan default filename / sheet_name untuk CSV.
+// Field CSV tetap string; tipe di-infer per value lewat coerce_numeric_strings (default true di sini), jadi
+// "42" jadi angka sedangkan "007" dan NIP 18 digit tetap teks. column_types tetap didahulukan
+fn parse_csv_options(header: Option<&str>) -> Result<ExportOptions, ExcelError> {
+    let invalid = |cause: String| ExcelError::InvalidOptions(format!("Invalid X-Options header: {}", cause));
+    let mut options = match header {
+        Some(json) => serde_json::from_str::<Value>(json).map_err(|e| invalid(e.to_string()))?,
+        None => Value::Object(serde_json::Map::new()),
+    };
+    let object = options
+        .as_object_mut()
+        .ok_or_else(|| invalid("expected a JSON object".to_string()))?;
+    object.entry("filename").or_insert_with(|| Value::from("converted.xlsx"));
+    object.entry("sheet_name").or_insert_with(|| Value::from("Sheet1"));
+    object.entry("coerce_numeric_strings").or_insert(Value::Bool(true));
+    serde_json::from_value(options).map_err(|e| invalid(e.to_string()))
+}
+
+// Convert CSV to Excel; mengembalikan file beserta jumlah record CSV untuk metrics
+async fn convert_csv_to_excel(csv_content: String, options: ExportOptions) -> anyhow::Result<(Vec<u8>, usize)> {
+    let (headers, records) = run_blocking_with_timeout(move || parse_csv_records(&csv_content)).await?;
+    let records_processed = records.len();
+    
+    // Write path sama dengan /generate-excel; options.headers (kalau ada) memilih / mengurutkan kolom CSV
+    let req = ExportRequest {
+        data: records,
+        options: ExportOptions {
+            headers: options.headers.clone().or(Some(headers)),
+            ..options
+        },
+    };
+    
+    Ok((generate_excel_file(req).await?, records_processed))
+}
+
+// Parse CSV jadi headers + records JSON (semua value string)
+fn parse_csv_records(csv_content: &str) -> anyhow::Result<(Vec<String>, Vec<Value>)> {
+    info!("📝 Parsing CSV content");
+    
+    // Parse CSV
+    let mut reader = Reader::from_reader(csv_content.as_bytes());
+    let mut records = Vec::new();
+    let mut headers = Vec::new();
+    
+    // Get headers
+    if let Ok(header_record) = reader.headers() {
+        headers = header_record.iter().map(|h| h.to_string()).collect();
+    }
+    
+    // Get all records
+    for result in reader.records() {
+        let record = result.map_err(|e| ExcelError::InvalidRecord(format!("Invalid CSV record: {}", e)))?;
+        let mut row_data = serde_json::Map::new();
+        
+        // Field kosong dianggap null supaya null_value / allow_empty berlaku seperti di JSON
+        for (i, field) in record.iter().enumerate() {
+            if let Some(header) = headers.get(i) {
+                let value = if field.is_empty() { Value::Null } else { Value::String(field.to_string()) };
+                row_data.insert(header.clone(), value);
+            }
+        }
+        records.push(Value::Object(row_data));
+    }
+    
+    info!("📊 Parsed {} records with {} columns", records.len(), headers.len());
+    Ok((headers, records))
+}
+
+// Test endpoint untuk cek service
+async fn test_handler() -> Result<impl warp::Reply, warp::Rejection> {
+    info!("🧪 Test endpoint called");
+    
+    let req = ExportRequest {
+        data: sample_records(),
+        options: ExportOptions {
+            filename: "test.xlsx".to_string(),
+            sheet_name: Some("Test".to_string()),
+            headers: None,
+            ..Default::default()
+        },
+    };
+    
+    generate_excel_handler(req, 0, GenerateParams::default(), None, None).await
+}
+
+// Sample data with NIP untuk /test dan GET /preview
+fn sample_records() -> Vec<Value> {
+    vec![
+        serde_json::json!({
+            "id": 1,
+            "name": "John Doe",
+            "nip": "199103052019031008",
+            "email": "john@example.com",
+            "age": 30,
+            "city": "Jakarta"
+        }),
+        serde_json::json!({
+            "id": 2,
+            "name": "Jane Smith", 
+            "nip": "198712142020121005",
+            "email": "jane@example.com",
+            "age": 25,
+            "city": "Surabaya"
+        })
+    ]
+}
+
+// Custom error types
+#[derive(Debug)]
+enum ExcelError {
+    Unauthorized(String),
+    EmptyData(String),
+    TooManyRows(String),
+    TooManyColumns(String),
+    InvalidRecord(String),
+    InvalidRecordShape(String),
+    SchemaViolation(String),
+    InvalidOptions(String),
+    UnsupportedEncoding(String),
+    PayloadTooLarge(String),
+    ServiceBusy(String),
+    UrlNotAllowed(String),
+    DownloadTooLarge(String),
+    FetchFailed(String),
+    Timeout(String),
+    GenerationFailed(String),
+    JobNotFound(String),
+    JobNotReady(String),
+    IdempotencyKeyReused(String),
+}
+
+impl ExcelError {
+    // Kode error yang stabil supaya front-end bisa branching berdasarkan penyebab
+    fn error_code(&self) -> &'static str {
+        match self {
+            ExcelError::Unauthorized(_) => "UNAUTHORIZED",
+            ExcelError::EmptyData(_) => "EMPTY_DATA",
+            ExcelError::TooManyRows(_) => "TOO_MANY_ROWS",
+            ExcelError::TooManyColumns(_) => "TOO_MANY_COLUMNS",
+            ExcelError::InvalidRecord(_) => "INVALID_RECORD",
+            ExcelError::InvalidRecordShape(_) => "INVALID_RECORD_SHAPE",
+            ExcelError::SchemaViolation(_) => "SCHEMA_VIOLATION",
+            ExcelError::InvalidOptions(_) => "INVALID_OPTIONS",
+            ExcelError::UnsupportedEncoding(_) => "UNSUPPORTED_ENCODING",
+            ExcelError::PayloadTooLarge(_) => "PAYLOAD_TOO_LARGE",
+            ExcelError::ServiceBusy(_) => "SERVICE_BUSY",
+            ExcelError::UrlNotAllowed(_) => "URL_NOT_ALLOWED",
+            ExcelError::DownloadTooLarge(_) => "DOWNLOAD_TOO_LARGE",
+            ExcelError::FetchFailed(_) => "FETCH_FAILED",
+            ExcelError::Timeout(_) => "GENERATION_TIMEOUT",
+            ExcelError::GenerationFailed(_) => "XLSX_WRITE_FAILED",
+            ExcelError::JobNotFound(_) => "JOB_NOT_FOUND",
+            ExcelError::JobNotReady(_) => "JOB_NOT_READY",
+            ExcelError::IdempotencyKeyReused(_) => "IDEMPOTENCY_KEY_REUSED",
+        }
+    }
+
+    fn status_code(&self) -> warp::http::StatusCode {
+        match self {
+            ExcelError::Unauthorized(_) => warp::http::StatusCode::UNAUTHORIZED,
+            ExcelError::EmptyData(_)
+            | ExcelError::InvalidRecord(_)
+            | ExcelError::InvalidOptions(_) => warp::http::StatusCode::BAD_REQUEST,
+            ExcelError::InvalidRecordShape(_)
+            | ExcelError::SchemaViolation(_)
+            | ExcelError::IdempotencyKeyReused(_) => warp::http::StatusCode::UNPROCESSABLE_ENTITY,
+            ExcelError::TooManyRows(_)
+            | ExcelError::TooManyColumns(_)
+            | ExcelError::PayloadTooLarge(_)
+            | ExcelError::DownloadTooLarge(_) => warp::http::StatusCode::PAYLOAD_TOO_LARGE,
+            ExcelError::UrlNotAllowed(_) => warp::http::StatusCode::FORBIDDEN,
+            ExcelError::JobNotFound(_) => warp::http::StatusCode::NOT_FOUND,
+            ExcelError::JobNotReady(_) => warp::http::StatusCode::CONFLICT,
+            ExcelError::ServiceBusy(_) => warp::http::StatusCode::SERVICE_UNAVAILABLE,
+            ExcelError::UnsupportedEncoding(_) => warp::http::StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ExcelError::FetchFailed(_) => warp::http::StatusCode::BAD_GATEWAY,
+            ExcelError::Timeout(_) => warp::http::StatusCode::GATEWAY_TIMEOUT,
+            ExcelError::GenerationFailed(_) => warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ExcelError::Unauthorized(msg)
+            | ExcelError::EmptyData(msg)
+            | ExcelError::TooManyRows(msg)
+            | ExcelError::TooManyColumns(msg)
+            | ExcelError::InvalidRecord(msg)
+            | ExcelError::InvalidRecordShape(msg)
+            | ExcelError::SchemaViolation(msg)
+            | ExcelError::InvalidOptions(msg)
+            | ExcelError::UnsupportedEncoding(msg)
+            | ExcelError::PayloadTooLarge(msg)
+            | ExcelError::ServiceBusy(msg)
+            | ExcelError::UrlNotAllowed(msg)
+            | ExcelError::DownloadTooLarge(msg)
+            | ExcelError::FetchFailed(msg)
+            | ExcelError::Timeout(msg)
+            | ExcelError::GenerationFailed(msg)
+            | ExcelError::JobNotFound(msg)
+            | ExcelError::JobNotReady(msg)
+            | ExcelError::IdempotencyKeyReused(msg) => msg,
+        }
+    }
+}
+
+impl std::fmt::Display for ExcelError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.error_code(), self.message())
+    }
+}
+
+impl std::error::Error for ExcelError {}
+
+impl warp::reject::Reject for ExcelError {}
+
+// Ubah error dari generate ke rejection; error selain ExcelError dianggap gagal menulis xlsx
+fn to_rejection(e: anyhow::Error) -> warp::Rejection {
+    warp::reject::custom(to_excel_error(e))
+}
+
+fn to_excel_error(e: anyhow::Error) -> ExcelError {
+    e.downcast::<ExcelError>()
+        .unwrap_or_else(|e| ExcelError::GenerationFailed(e.to_string()))
+}
+
+// Error handler
+async fn handle_rejection(err: warp::Rejection) -> Result<impl warp::Reply, Infallible> {
+    // Error dari handler dicek sebelum MethodNotAllowed: untuk path dengan GET dan POST (/preview), rejection
+    // gabungan juga berisi MethodNotAllowed dari route method lain
+    let (code, message, error_code) = if let Some(e) = err.find::<ExcelError>() {
+        (e.status_code(), e.message().to_string(), e.error_code())
+    } else if let Some(e) = err.find::<warp::body::BodyDeserializeError>() {
+        // Route dengan warp::body::json(); pesan serde menyebut field yang hilang / salah tipe
+        let cause = std::error::Error::source(e).map_or_else(|| e.to_string(), |cause| cause.to_string());
+        let e = invalid_json_body(cause);
+        (e.status_code(), e.message().to_string(), e.error_code())
+    } else if err.is_not_found() {
+        (warp::http::StatusCode::NOT_FOUND, "Not Found".to_string(), "NOT_FOUND")
+    } else if err.find::<warp::reject::MethodNotAllowed>().is_some() {
+        (warp::http::StatusCode::METHOD_NOT_ALLOWED, "Method Not Allowed".to_string(), "METHOD_NOT_ALLOWED")
+    } else {
+        error!("Unhandled rejection: {:?}", err);
+        (warp::http::StatusCode::INTERNAL_SERVER_ERROR, "Internal Server Error".to_string(), "INTERNAL_ERROR")
+    };
+
+    let json = warp::reply::json(&ApiResponse {
+        success: false,
+        message,
+        error_code: Some(error_code.to_string()),
+        records_processed: None,
+        processing_time_ms: None,
+    });
+
+    let mut response = warp::reply::with_status(json, code).into_response();
+    if matches!(err.find::<ExcelError>(), Some(ExcelError::ServiceBusy(_))) {
+        response
+            .headers_mut()
+            .insert("retry-after", warp::http::HeaderValue::from_static("1"));
+    }
+    Ok(response)
+}
+
+// Body JSON (opsional gzip / deflate) beserta hash body setelah didekompresi, untuk Idempotency-Key
+fn json_body_with_encoding<T: DeserializeOwned + Send>(
+    max_size: u64,
+) -> impl Filter<Extract = (T, u64), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("content-encoding")
+        .and(warp::body::bytes())
+        .and_then(move |encoding: Option<String>, body: bytes::Bytes| async move {
+            decode_json_body(encoding.as_deref(), &body, max_size).map_err(warp::reject::custom)
+        })
+        .untuple_one()
+}
+
+// Hash isi body (bukan hash kriptografis): cukup untuk mendeteksi Idempotency-Key yang dipakai ulang
+// dengan body berbeda, key-nya sendiri yang harus unik dan sulit ditebak
+fn body_digest(body: &[u8]) -> u64 {
+    use std::hash::{Hash, Hasher};
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    body.hash(&mut hasher);
+    hasher.finish()
+}
+
+// JSON body yang boleh dikompres (Content-Encoding: gzip / deflate). Ukuran hasil dekompresi
+// juga dibatasi max_size supaya body kecil tidak bisa mengembang tanpa batas.
+fn decode_json_body<T: DeserializeOwned>(encoding: Option<&str>, body: &[u8], max_size: u64) -> Result<(T, u64), ExcelError> {
+    let encoding = encoding.map(|e| e.trim().to_ascii_lowercase());
+    let mut decoded = Vec::new();
+    let reader: Box<dyn std::io::Read + '_> = match encoding.as_deref() {
+        None | Some("") | Some("identity") => Box::new(body),
+        Some("gzip") | Some("x-gzip") => Box::new(flate2::read::GzDecoder::new(body)),
+        Some("deflate") => Box::new(flate2::read::ZlibDecoder::new(body)),
+        Some(other) => {
+            return Err(ExcelError::UnsupportedEncoding(format!(
+                "Unsupported Content-Encoding '{}': use gzip or deflate",
+                other
+            )));
+        }
+    };
+    reader
+        .take(max_size + 1)
+        .read_to_end(&mut decoded)
+        .map_err(|e| ExcelError::InvalidRecord(format!("Failed to decompress request body: {}", e)))?;
+    if decoded.len() as u64 > max_size {
+        return Err(ExcelError::PayloadTooLarge(format!(
+            "Decompressed request body exceeds {}MB (EXCEL_MAX_BODY_SIZE_MB)",
+            max_size / (1024 * 1024)
+        )));
+    }
+    let value = serde_json::from_slice(&decoded).map_err(invalid_json_body)?;
+    Ok((value, body_digest(&decoded)))
+}
+
+// Mis. "Invalid JSON body: missing field `filename` at line 1 column 42"
+fn invalid_json_body(cause: impl std::fmt::Display) -> ExcelError {
+    ExcelError::InvalidRecord(format!("Invalid JSON body: {}", cause))
+}
+
+// Filter auth: cek header "Authorization: Bearer <token>" kalau token dikonfigurasi (None = auth tidak aktif)
+fn require_bearer_token(
+    token: Option<String>,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let token = token.clone();
+            async move {
+                let Some(token) = token else {
+                    return Ok(());
+                };
+                match authorization.as_deref().and_then(bearer_token) {
+                    Some(provided) if constant_time_eq(provided.as_bytes(), token.as_bytes()) => Ok(()),
+                    Some(_) => Err(warp::reject::custom(ExcelError::Unauthorized("Invalid bearer token".to_string()))),
+                    None => Err(warp::reject::custom(ExcelError::Unauthorized("Missing bearer token".to_string()))),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+// Token dari "Authorization: Bearer <token>"; scheme case-insensitive (RFC 7235)
+fn bearer_token(authorization: &str) -> Option<&str> {
+    let (scheme, token) = authorization.trim_start().split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+// Perbandingan yang waktunya tidak tergantung posisi byte pertama yang berbeda, supaya token tidak
+// bisa ditebak lewat timing. Panjang token tetap bocor, tapi itu bukan rahasia
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+// CORS filter
+fn cors() -> warp::filters::cors::Builder {
+    warp::cors()
+        .allow_any_origin()
+        .allow_headers(vec!["content-type", "authorization", "idempotency-key", "x-debug", "x-options"])
+        .allow_methods(vec!["GET", "POST", "PUT", "DELETE", "OPTIONS"])
+        // Supaya browser bisa membaca nama file, metadata ?meta=header, X-Cache, X-Request-Id dan X-Skipped-Rows
+        .expose_headers(vec![
+            "content-disposition",
+            "x-records-processed",
+            "x-processing-time-ms",
+            "x-cache",
+            "x-request-id",
+            "x-skipped-rows",
+            "x-skipped-rows-count",
+        ])
+}
+
+#[tokio::main]
+async fn main() {
+    init_logger();
+    
+    info!("🚀 Starting Excel Service v{}", env!("CARGO_PKG_VERSION"));
+    
+    // Health check route
+    let health = warp::path("health")
+        .and(warp::path::end())
+        .and(warp::get())
+        .and_then(health_handler);
+    
+    // Deep health check (benar-benar menulis workbook)
+    let deep_health = warp::path!("health" / "deep")
+        .and(warp::get())
+        .and_then(deep_health_handler);
+    
+    // Test route
+    let test = warp::path("test")
+        .and(warp::get())
+        .and_then(test_handler);
+    
+    // Get max body size from environment (default 2GB for 24GB server)
+    let max_body_size = std::env::var("EXCEL_MAX_BODY_SIZE_MB")
+        .unwrap_or_else(|_| "2048".to_string())
+        .parse::<u64>()
+        .unwrap_or(2048) * 1024 * 1024;
+
+    // Bearer token opsional; kalau API_TOKEN tidak di-set, semua request diterima
+    let api_token = std::env::var("API_TOKEN").ok().filter(|token| !token.is_empty());
+    let auth = require_bearer_token(api_token.clone());
+
+    // Main Excel generation route
+    let generate = warp::path("generate-excel")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::content_length_limit(max_body_size)) // Configurable limit
+        .and(json_body_with_encoding(max_body_size))
+        .and(warp::query::<GenerateParams>())
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and(warp::header::optional::<String>("idempotency-key"))
+        .and_then(generate_excel_handler);
+    
+    // Progress job async=true sebagai Server-Sent Events
+    let job_progress = warp::path!("generate-excel" / "progress" / String)
+        .and(warp::get())
+        .and(auth.clone())
+        .and_then(job_progress_handler);
+    
+    // File hasil job async=true (sekali download)
+    let job_download = warp::path!("download" / String)
+        .and(warp::get())
+        .and(auth.clone())
+        .and_then(job_download_handler);
+    
+    // Generate dari JSON di URL lain (host dibatasi URL_ALLOWED_HOSTS)
+    let generate_from_url = warp::path("generate-excel-from-url")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .and_then(generate_excel_from_url_handler);
+    
+    // Multi-sheet Excel generation route
+    let generate_multi = warp::path("generate-excel-multi")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .and_then(generate_excel_multi_handler);
+    
+    // Multipart route: part "data" dan "options", tanpa harus menyusun satu body JSON besar
+    let generate_multipart = warp::path("generate-excel-multipart")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::multipart::form().max_length(max_body_size))
+        .and(warp::query::<GenerateParams>())
+        .and(warp::header::optional::<String>("accept-encoding"))
+        .and_then(generate_excel_multipart_handler);
+    
+    // Batch route: beberapa file dalam satu ZIP
+    let generate_batch = warp::path("generate-excel-batch")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .and_then(generate_excel_batch_handler);
+    
+    // Template route: data ditulis ke sheet di workbook .xlsx yang diupload
+    let generate_template = warp::path("generate-excel-template")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .and_then(generate_excel_template_handler);
+    
+    // Preview HTML table N record pertama; GET memakai sample data
+    let preview_sample = warp::path("preview")
+        .and(warp::get())
+        .and(auth.clone())
+        .and(warp::query::<PreviewParams>())
+        .and_then(preview_sample_handler);
+    
+    let preview = warp::path("preview")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::query::<PreviewParams>())
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .and_then(preview_handler);
+    
+    // Dry-run route: skema kolom tanpa generate file
+    let inspect = warp::path("inspect")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .and_then(inspect_handler);
+    
+    // CSV generation route (request sama dengan generate-excel)
+    let generate_csv = warp::path("generate-csv")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .and_then(|req| generate_csv_handler(req, TextFormat::Csv));
+    
+    // TSV: sama dengan generate-csv, delimiter tab
+    let generate_tsv = warp::path("generate-tsv")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::body::content_length_limit(max_body_size))
+        .and(warp::body::json())
+        .and_then(|req| generate_csv_handler(req, TextFormat::Tsv));
+    
+    // NDJSON streaming route, tanpa content-length limit (dibatasi MAX_ROWS dan NDJSON_MAX_LINE_MB per baris)
+    let generate_stream = warp::path("generate-excel-stream")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::query::<StreamParams>())
+        .and(warp::header::optional::<String>("x-headers"))
+        .and(warp::body::stream())
+        .and_then(generate_excel_stream_handler);
+    
+    // CSV to Excel route
+    let csv_to_excel = warp::path("csv-to-excel")
+        .and(warp::post())
+        .and(auth.clone())
+        .and(warp::header::exact("content-type", "text/csv"))
+        .and(warp::header::optional::<String>("x-options"))
+        .and(warp::body::content_length_limit(max_body_size / 2)) // Half for CSV
+        .and(warp::body::bytes())
+        .and_then(csv_to_excel_handler);
+    
+    // Status endpoint
+    let status = warp::path("status")
+        .and(warp::get())
+        .map(|| {
+            let response = serde_json::json!({
+                "service": "excel-service",
+                "status": "running",
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+                "memory_usage": get_memory_usage(),
+                "active_jobs": ACTIVE_JOBS.load(Ordering::SeqCst)
+            });
+            warp::reply::json(&response)
+        });
+    
+    // Prometheus metrics
+    let metrics = warp::path("metrics")
+        .and(warp::get())
+        .map(|| {
+            warp::reply::with_header(METRICS.render(), "content-type", "text/plain; version=0.0.4")
+        });
+    
+    // Combine all routes
+    let routes = health
+        .or(deep_health)
+        .or(test)
+        .or(generate)
+        .or(job_progress)
+        .or(job_download)
+        .or(generate_from_url)
+        .or(generate_multi)
+        .or(generate_multipart)
+        .or(generate_batch)
+        .or(generate_template)
+        .or(generate_csv)
+        .or(generate_tsv)
+        .or(generate_stream)
+        .or(inspect)
+        .or(preview_sample)
+        .or(preview)
+        .or(csv_to_excel)
+        .or(status)
+        .or(metrics)
+        .with(cors())
+        .recover(handle_rejection);
+    
+    let port = std::env::var("PORT")
+        .unwrap_or_else(|_| "3333".to_string())
+        .parse::<u16>()
+        .unwrap_or(3333);
+    
+    info!("🦀 Excel Service running on http://0.0.0.0:{}", port);
+    info!("🔧 Configuration:");
+    info!("   - Chunk size: {}", std::env::var("EXCEL_CHUNK_SIZE").unwrap_or("5000".to_string()));
+    info!("   - Max body size: {}MB", max_body_size / (1024 * 1024));
+    info!("   - Memory limit: {}MB", std::env::var("EXCEL_MAX_MEMORY_MB").unwrap_or("6144".to_string()));
+    info!("   - Temp dir: {}", temp_dir_path().display());
+    info!("   - Max rows: {}", max_rows());
+    info!("   - Max columns: {}", max_columns());
+    info!("   - Generation timeout: {}s", generation_timeout().as_secs());
+    info!("   - Max concurrent: {}", max_concurrent().map_or("unlimited".to_string(), |limit| limit.to_string()));
+    info!("   - URL allowed hosts: {:?}", url_allowed_hosts());
+    info!("   - Auth: {}", if api_token.is_some() { "bearer token" } else { "disabled" });
+    info!("📋 Available endpoints:");
+    info!("   GET  /health        - Health check");
+    info!("   GET  /health/deep   - Health check that writes a test workbook");
+    info!("   GET  /test          - Test with sample data");
+    info!("   GET  /status        - Service status");
+    info!("   GET  /metrics       - Prometheus metrics");
+    info!("   POST /generate-excel - Generate Excel file (?async=true returns a job_id)");
+    info!("   GET  /generate-excel/progress/:job_id - Async job progress (Server-Sent Events)");
+    info!("   GET  /download/:job_id - Download the file of a finished async job (once)");
+    info!("   POST /generate-excel-from-url - Generate Excel file from JSON at an allowed URL");
+    info!("   POST /generate-excel-multi - Generate Excel file with multiple sheets");
+    info!("   POST /generate-excel-multipart - Generate Excel file from multipart data/options parts");
+    info!("   POST /generate-excel-batch - Generate several Excel files as one ZIP");
+    info!("   POST /generate-excel-template - Fill a sheet of an uploaded .xlsx template");
+    info!("   POST /generate-csv  - Generate CSV file");
+    info!("   POST /generate-tsv  - Generate TSV (tab-separated) file");
+    info!("   POST /generate-excel-stream - Generate Excel file from NDJSON stream");
+    info!("   POST /inspect       - Preview detected columns and types");
+    info!("   GET/POST /preview   - First N rows as an HTML table (?rows=20)");
+    info!("   POST /csv-to-excel  - Convert CSV to Excel (Content-Type: text/csv)");
+    
+    // Setiap request dibungkus serve_with_request_id (X-Request-Id + access log)
+    let service = warp::service(routes);
+    let make_service = warp::hyper::service::make_service_fn(move |conn: &warp::hyper::server::conn::AddrStream| {
+        let service = service.clone();
+        let remote_addr = conn.remote_addr();
+        async move {
+            Ok::<_, Infallible>(warp::hyper::service::service_fn(move |req| {
+                serve_with_request_id(service.clone(), remote_addr, req)
+            }))
+        }
+    });
+    
+    // Graceful shutdown: request yang sedang berjalan diselesaikan dulu (temp file ikut dibersihkan)
+    let server = warp::hyper::Server::bind(&([0, 0, 0, 0], port).into())
+        .serve(make_service)
+        .with_graceful_shutdown(shutdown_signal());
+    if let Err(e) = server.await {
+        error!("❌ Server error: {}", e);
+    }
+    wait_for_job_tasks().await;
+    
+    info!("👋 Excel Service stopped");
+}
+
+// Selesai saat menerima Ctrl+C atau SIGTERM (Unix)
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("❌ Failed to listen for Ctrl+C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("❌ Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    
+    tokio::select! {
+        _ = ctrl_c => info!("🛑 Received Ctrl+C"),
+        _ = terminate => info!("🛑 Received SIGTERM"),
+    }
+    info!("⏳ Shutting down, waiting for {} active job(s) to finish...", ACTIVE_JOBS.load(Ordering::SeqCst));
+    // Request HTTP yang sedang berjalan ditunggu graceful shutdown hyper, job async=true oleh wait_for_job_tasks
+}
+
+// Helper function untuk get memory usage
+fn get_memory_usage() -> HashMap<String, String> {
+    let mut usage = HashMap::new();
+    
+    #[cfg(target_os = "linux")]
+    {
+        if let Ok(contents) = std::fs::read_to_string("/proc/self/status") {
+            for line in contents.lines() {
+                if line.starts_with("VmRSS:") {
+                    usage.insert("rss".to_string(), line.split_whitespace().nth(1).unwrap_or("0").to_string());
+                }
+                if line.starts_with("VmSize:") {
+                    usage.insert("virtual".to_string(), line.split_whitespace().nth(1).unwrap_or("0").to_string());
+                }
+            }
+        }
+    }
+    
+    #[cfg(not(target_os = "linux"))]
+    {
+        usage.insert("platform".to_string(), "not_linux".to_string());
+    }
+    
+    usage
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_filename_strips_path_components() {
+        assert_eq!(sanitize_filename("../../etc/passwd", "xlsx").unwrap(), "passwd.xlsx");
+        assert_eq!(sanitize_filename("..\\..\\laporan.xlsx", "xlsx").unwrap(), "laporan.xlsx");
+        assert!(matches!(sanitize_filename("../", "xlsx"), Err(ExcelError::InvalidOptions(_))));
+        assert!(matches!(sanitize_filename("..", "xlsx"), Err(ExcelError::InvalidOptions(_))));
+    }
+
+    #[test]
+    fn sanitize_filename_strips_leading_dots() {
+        assert_eq!(sanitize_filename(".hidden", "xlsx").unwrap(), "hidden.xlsx");
+        assert_eq!(sanitize_filename("...report.csv", "csv").unwrap(), "report.csv");
+    }
+
+    #[test]
+    fn sanitize_filename_rejects_control_characters() {
+        assert!(matches!(sanitize_filename("a\0b.xlsx", "xlsx"), Err(ExcelError::InvalidOptions(_))));
+        assert!(matches!(sanitize_filename("a\nb.xlsx", "xlsx"), Err(ExcelError::InvalidOptions(_))));
+    }
+
+    #[test]
+    fn sanitize_filename_rejects_empty_names() {
+        assert!(matches!(sanitize_filename("", "xlsx"), Err(ExcelError::InvalidOptions(_))));
+        assert!(matches!(sanitize_filename("   ", "xlsx"), Err(ExcelError::InvalidOptions(_))));
+    }
+
+    #[test]
+    fn sanitize_filename_normalizes_extension() {
+        assert_eq!(sanitize_filename("report", "xlsx").unwrap(), "report.xlsx");
+        assert_eq!(sanitize_filename("report.xlsx", "csv").unwrap(), "report.csv");
+        assert_eq!(sanitize_filename("report.XLSX", "ods").unwrap(), "report.ods");
+        assert_eq!(sanitize_filename("data.v2", "xlsx").unwrap(), "data.v2.xlsx");
+    }
+
+    #[test]
+    fn autofit_widens_long_columns() {
+        let rows = vec![vec![CellValue::String("a very long description value".to_string()), CellValue::Integer(7)]];
+        let widths = autofit_column_widths(&["description", "id"], &rows);
+        assert_eq!(widths, vec![31.0, 4.0]);
+        assert!(widths[0] > widths[1]);
+    }
+
+    #[test]
+    fn autofit_clamps_to_max_width() {
+        let rows = vec![vec![CellValue::String("x".repeat(500))]];
+        let widths = autofit_column_widths(&["notes"], &rows);
+        assert_eq!(widths, vec![AUTOFIT_MAX_WIDTH as f64]);
+    }
+
+    #[test]
+    fn autofit_counts_characters_not_bytes() {
+        // "é" 2 byte di UTF-8 tapi satu karakter; huruf CJK tampil dua kali lebar
+        let rows = vec![vec![CellValue::String("café".to_string()), CellValue::String("東京都".to_string())]];
+        let widths = autofit_column_widths(&["a", "b"], &rows);
+        assert_eq!(widths, vec![6.0, 8.0]);
+        assert_eq!(text_display_width("서울"), 4);
+    }
+
+    #[test]
+    fn autofit_header_only_uses_header_length() {
+        let widths = autofit_column_widths(&["first_name", "id"], &[]);
+        assert_eq!(widths, vec![12.0, 4.0]);
+    }
+
+    fn csv_request(data: Value) -> ExportRequest {
+        serde_json::from_value(serde_json::json!({ "data": data, "options": { "filename": "test.csv" } })).unwrap()
+    }
+
+    #[test]
+    fn write_csv_quotes_special_fields() {
+        let req = csv_request(serde_json::json!([
+            { "name": "Doe, John", "note": "He said \"hi\"", "address": "Jl. Merdeka\nJakarta" }
+        ]));
+        let csv = String::from_utf8(write_csv(req, TextFormat::Csv).unwrap()).unwrap();
+        assert_eq!(csv, "name,note,address\n\"Doe, John\",\"He said \"\"hi\"\"\",\"Jl. Merdeka\nJakarta\"\n");
+    }
+
+    #[test]
+    fn write_csv_round_trips_through_parser() {
+        let req = csv_request(serde_json::json!([{ "name": "Doe, \"JD\" John", "city": "Bandung" }]));
+        let csv = String::from_utf8(write_csv(req, TextFormat::Csv).unwrap()).unwrap();
+        let (headers, records) = parse_csv_records(&csv).unwrap();
+        assert_eq!(headers, vec!["name", "city"]);
+        assert_eq!(records, vec![serde_json::json!({ "name": "Doe, \"JD\" John", "city": "Bandung" })]);
+    }
+
+    #[test]
+    fn write_tsv_uses_tab_delimiter() {
+        let req = csv_request(serde_json::json!([{ "a": "x\ty", "b": 1 }]));
+        let tsv = String::from_utf8(write_csv(req, TextFormat::Tsv).unwrap()).unwrap();
+        assert_eq!(tsv, "a\tb\n\"x\ty\"\t1\n");
+    }
+
+    #[test]
+    fn parse_csv_records_handles_quoted_fields() {
+        let csv = "id,name,notes\n1,\"Doe, John\",\"line one\nline two\"\n2,\"say \"\"hi\"\"\",\n";
+        let (headers, records) = parse_csv_records(csv).unwrap();
+        assert_eq!(headers, vec!["id", "name", "notes"]);
+        assert_eq!(records, vec![
+            serde_json::json!({ "id": "1", "name": "Doe, John", "notes": "line one\nline two" }),
+            serde_json::json!({ "id": "2", "name": "say \"hi\"", "notes": null }),
+        ]);
+    }
+
+    #[test]
+    fn parse_csv_records_rejects_ragged_rows() {
+        let err = parse_csv_records("a,b\n1,2,3\n").unwrap_err();
+        assert!(matches!(err.downcast_ref::<ExcelError>(), Some(ExcelError::InvalidRecord(_))));
+    }
+
+    #[test]
+    fn non_finite_floats_default_to_empty_cell() {
+        let options = ExportOptions { null_value: Some("-".to_string()), ..Default::default() };
+        assert_eq!(float_to_cell(f64::NAN, &options), CellValue::Empty);
+        assert_eq!(float_to_cell(f64::INFINITY, &options), CellValue::Empty);
+        assert_eq!(float_to_cell(f64::NEG_INFINITY, &options), CellValue::Empty);
+        assert_eq!(float_to_cell(1.5, &options), CellValue::Float(1.5));
+    }
+
+    #[test]
+    fn non_finite_floats_use_configured_text() {
+        let options = ExportOptions { non_finite_as: Some("#NUM!".to_string()), ..Default::default() };
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            assert_eq!(float_to_cell(value, &options), CellValue::String("#NUM!".to_string()));
+        }
+        assert_eq!(float_to_cell(-0.25, &options), CellValue::Float(-0.25));
+    }
+
+    #[test]
+    fn autofit_ignores_missing_cells() {
+        let rows = vec![vec![CellValue::Empty], vec![]];
+        let widths = autofit_column_widths(&["status", "code"], &rows);
+        assert_eq!(widths, vec![8.0, 6.0]);
+    }
+
+    #[test]
+    fn parse_numeric_string_accepts_plain_numbers() {
+        assert_eq!(parse_numeric_string("42"), Some(CellValue::Integer(42)));
+        assert_eq!(parse_numeric_string("-3.5"), Some(CellValue::Float(-3.5)));
+        assert_eq!(parse_numeric_string("0"), Some(CellValue::Integer(0)));
+        assert_eq!(parse_numeric_string("0.25"), Some(CellValue::Float(0.25)));
+    }
+
+    #[test]
+    fn parse_numeric_string_keeps_other_strings_as_text() {
+        for s in ["007", "123abc", "", "-", "1.", ".5", "1e5", "1,000", " 42", "+1", "1.2.3", "NaN"] {
+            assert_eq!(parse_numeric_string(s), None, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn parse_numeric_string_keeps_unsafe_integers_exact() {
+        assert_eq!(parse_numeric_string("9007199254740993"), Some(CellValue::String("9007199254740993".to_string())));
+        assert_eq!(parse_numeric_string("99999999999999999999"), None);
+    }
+
+    #[test]
+    fn parse_hex_color_accepts_hex_digits() {
+        assert_eq!(parse_hex_color("FF0000").unwrap(), 0xFF0000);
+        assert_eq!(parse_hex_color("#00ff00").unwrap(), 0x00FF00);
+        assert_eq!(parse_hex_color(" 0000FF ").unwrap(), 0x0000FF);
+    }
+
+    #[test]
+    fn parse_hex_color_rejects_malformed_colors() {
+        for hex in ["", "#", "FFF", "FF00000", "GG0000", "red", "+FFFFF"] {
+            assert!(matches!(parse_hex_color(hex), Err(ExcelError::InvalidOptions(_))), "{:?}", hex);
+        }
+    }
+
+    #[test]
+    fn html_escape_escapes_markup() {
+        assert_eq!(
+            html_escape("<a href=\"x\">Tom & Jerry's</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;"
+        );
+        assert_eq!(html_escape("Budi Santoso"), "Budi Santoso");
+    }
+
+    #[test]
+    fn preview_html_escapes_cells_and_headers() {
+        let req: ExportRequest = serde_json::from_value(serde_json::json!({
+            "data": [{ "name": "<script>alert(1)</script>", "id": 1 }, { "name": "second", "id": 2 }],
+            "options": { "filename": "preview.xlsx" }
+        }))
+        .unwrap();
+        let html = preview_html(req, 1).unwrap();
+        assert!(html.contains("<th>name</th><th>id</th>"));
+        assert!(html.contains("<td>&lt;script&gt;alert(1)&lt;/script&gt;</td>"));
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("second"));
+    }
+
+    fn sheet_spec(name: &str, index: Option<usize>) -> SheetSpec {
+        SheetSpec {
+            sheet_name: Some(name.to_string()),
+            data: Vec::new(),
+            headers: None,
+            tab_color: None,
+            index,
+        }
+    }
+
+    fn sheet_names(sheets: &[SheetSpec]) -> Vec<&str> {
+        sheets.iter().map(|sheet| sheet.sheet_name.as_deref().unwrap_or_default()).collect()
+    }
+
+    #[test]
+    fn order_sheets_follows_indices() {
+        let sheets = order_sheets(vec![sheet_spec("Second", Some(1)), sheet_spec("First", Some(0))]).unwrap();
+        assert_eq!(sheet_names(&sheets), vec!["First", "Second"]);
+    }
+
+    #[test]
+    fn order_sheets_fills_remaining_slots_in_array_order() {
+        let sheets = order_sheets(vec![
+            sheet_spec("A", None),
+            sheet_spec("Summary", Some(0)),
+            sheet_spec("B", None),
+        ])
+        .unwrap();
+        assert_eq!(sheet_names(&sheets), vec!["Summary", "A", "B"]);
+
+        let unchanged = order_sheets(vec![sheet_spec("X", None), sheet_spec("Y", None)]).unwrap();
+        assert_eq!(sheet_names(&unchanged), vec!["X", "Y"]);
+    }
+
+    #[test]
+    fn order_sheets_rejects_duplicate_and_out_of_range_indices() {
+        let duplicate = order_sheets(vec![sheet_spec("A", Some(0)), sheet_spec("B", Some(0))]);
+        assert!(matches!(duplicate, Err(ExcelError::InvalidOptions(_))));
+        let out_of_range = order_sheets(vec![sheet_spec("A", Some(2)), sheet_spec("B", None)]);
+        assert!(matches!(out_of_range, Err(ExcelError::InvalidOptions(_))));
+    }
+
+    fn numbered_records(count: usize) -> Vec<Value> {
+        (0..count).map(|i| serde_json::json!({ "id": i })).collect()
+    }
+
+    #[test]
+    fn split_overflowing_sheets_creates_continuation_sheets() {
+        let options = ExportOptions { on_row_overflow: Some(RowOverflow::Split), ..Default::default() };
+        let mut data = sheet_spec("Data", None);
+        data.data = numbered_records(5);
+        data.data[4] = serde_json::json!({ "id": 4, "extra": true });
+        let sheets = split_overflowing_sheets(vec![data], &options, 2).unwrap();
+
+        assert_eq!(sheet_names(&sheets), vec!["Data", "Data (2)", "Data (3)"]);
+        assert_eq!(sheets.iter().map(|sheet| sheet.data.len()).collect::<Vec<_>>(), vec![2, 2, 1]);
+        assert_eq!(sheets[2].data[0]["id"], 4);
+        // Kolom dari record terakhir tetap ada di sheet pertama
+        for sheet in &sheets {
+            assert_eq!(sheet.headers.as_deref(), Some(&["id".to_string(), "extra".to_string()][..]));
+        }
+    }
+
+    #[test]
+    fn split_overflowing_sheets_keeps_small_and_unnamed_sheets() {
+        let options = ExportOptions { on_row_overflow: Some(RowOverflow::Split), ..Default::default() };
+        let mut small = sheet_spec("Small", None);
+        small.data = numbered_records(2);
+        let mut unnamed = sheet_spec("", None);
+        unnamed.sheet_name = None;
+        unnamed.data = numbered_records(3);
+        let sheets = split_overflowing_sheets(vec![small, unnamed], &options, 2).unwrap();
+
+        assert_eq!(sheets.len(), 3);
+        assert!(sheets[0].headers.is_none());
+        assert!(sheets[1].sheet_name.is_none() && sheets[2].sheet_name.is_none());
+    }
+
+    #[test]
+    fn split_overflowing_sheets_rejects_by_default() {
+        let mut data = sheet_spec("Data", None);
+        data.data = numbered_records(3);
+        let result = split_overflowing_sheets(vec![data], &ExportOptions::default(), 2);
+        assert!(matches!(result, Err(ExcelError::TooManyRows(_))));
+    }
+
+    #[test]
+    fn continuation_sheet_name_stays_within_31_chars() {
+        assert_eq!(continuation_sheet_name("Data", 2), "Data (2)");
+        let name = continuation_sheet_name(&"x".repeat(31), 12);
+        assert_eq!(name.chars().count(), 31);
+        assert!(name.ends_with(" (12)"));
+    }
+
+    #[test]
+    fn sheet_row_capacity_reserves_header_and_footer_rows() {
+        assert_eq!(sheet_row_capacity(&ExportOptions::default()), EXCEL_MAX_ROWS as usize - 1);
+        let options = ExportOptions {
+            title: Some("Laporan".to_string()),
+            summaries: Some(HashMap::from([("amount".to_string(), Aggregate::Sum)])),
+            ..Default::default()
+        };
+        assert_eq!(sheet_row_capacity(&options), EXCEL_MAX_ROWS as usize - 3);
+    }
+
+    #[test]
+    fn formula_column_refs_finds_cell_references() {
+        assert_eq!(formula_column_refs("=B{row}*C{row}"), vec!["B", "C"]);
+        assert_eq!(formula_column_refs("=SUM($A$2:A{row})"), vec!["A", "A"]);
+        assert_eq!(formula_column_refs("=aa{row}+1"), vec!["aa"]);
+    }
+
+    #[test]
+    fn formula_column_refs_skips_strings_functions_and_other_sheets() {
+        assert_eq!(formula_column_refs("=IF(A{row}>0,\"B2\",C{row})"), vec!["A", "C"]);
+        assert_eq!(formula_column_refs("=LOG10(D{row})"), vec!["D"]);
+        assert_eq!(formula_column_refs("=Sheet2!A1+Rates!$B$2*E{row}"), vec!["E"]);
+        assert!(formula_column_refs("=TODAY()").is_empty());
+    }
+
+    #[test]
+    fn formula_templates_rejects_columns_outside_data_block() {
+        let headers = vec!["qty".to_string(), "price".to_string(), "total".to_string()];
+        let options = ExportOptions {
+            formula_columns: Some(HashMap::from([("total".to_string(), "=A{row}*B{row}".to_string())])),
+            ..Default::default()
+        };
+        let templates = formula_templates(&headers, SheetLayout::new(&options), &options).unwrap();
+        assert_eq!(templates, vec![None, None, Some("=A{row}*B{row}".to_string())]);
+
+        let options = ExportOptions {
+            formula_columns: Some(HashMap::from([("total".to_string(), "=A{row}*D{row}".to_string())])),
+            ..Default::default()
+        };
+        assert!(formula_templates(&headers, SheetLayout::new(&options), &options).is_err());
+    }
+
+    #[test]
+    fn column_letters_round_trip() {
+        for (index, letters) in [(0, "A"), (25, "Z"), (26, "AA"), (27, "AB"), (16_383, "XFD")] {
+            assert_eq!(column_letters(index), letters);
+            assert_eq!(column_index(letters), index);
+        }
+    }
+
+    const BODY: &[u8] = br#"{"data": [{"id": 1}], "options": {"filename": "a.xlsx"}}"#;
+
+    #[test]
+    fn decode_json_body_accepts_plain_gzip_and_deflate() {
+        let (plain, plain_digest): (Value, u64) = decode_json_body(None, BODY, 1024).unwrap();
+        let (gzipped, gzipped_digest): (Value, u64) = decode_json_body(Some("gzip"), &gzip_bytes(BODY).unwrap(), 1024).unwrap();
+        let mut deflate = flate2::write::ZlibEncoder::new(Vec::new(), Compression::default());
+        deflate.write_all(BODY).unwrap();
+        let (deflated, _): (Value, u64) = decode_json_body(Some(" Deflate "), &deflate.finish().unwrap(), 1024).unwrap();
+
+        assert_eq!(plain["data"][0]["id"], 1);
+        assert_eq!(gzipped, plain);
+        assert_eq!(deflated, plain);
+        assert_eq!(decode_json_body::<Value>(Some("identity"), BODY, 1024).unwrap().0, plain);
+        // Digest dihitung dari body setelah didekompresi
+        assert_eq!(gzipped_digest, plain_digest);
+    }
+
+    #[test]
+    fn decode_json_body_rejects_unsupported_encoding() {
+        let result = decode_json_body::<Value>(Some("br"), BODY, 1024);
+        assert!(matches!(result, Err(ExcelError::UnsupportedEncoding(_))));
+    }
+
+    #[test]
+    fn decode_json_body_limits_decompressed_size() {
+        let big = format!("[{}]", vec!["0"; 10_000].join(","));
+        let result = decode_json_body::<Value>(Some("gzip"), &gzip_bytes(big.as_bytes()).unwrap(), 1024);
+        assert!(matches!(result, Err(ExcelError::PayloadTooLarge(_))));
+    }
+
+    #[test]
+    fn decode_json_body_reports_corrupt_input() {
+        assert!(matches!(decode_json_body::<Value>(Some("gzip"), BODY, 1024), Err(ExcelError::InvalidRecord(_))));
+        assert!(matches!(decode_json_body::<Value>(None, b"{\"data\":", 1024), Err(ExcelError::InvalidRecord(_))));
+    }
+
+    #[test]
+    fn accepts_gzip_parses_accept_encoding() {
+        assert!(accepts_gzip(Some("gzip")));
+        assert!(accepts_gzip(Some("br, GZIP;q=0.5")));
+        assert!(accepts_gzip(Some("deflate, gzip ; q=1")));
+        assert!(!accepts_gzip(None));
+        assert!(!accepts_gzip(Some("")));
+        assert!(!accepts_gzip(Some("deflate, br")));
+        assert!(!accepts_gzip(Some("gzip;q=0")));
+        assert!(!accepts_gzip(Some("x-gzip")));
+    }
+
+    #[test]
+    fn gzip_bytes_round_trips() {
+        let data = b"PK\x03\x04 not really a workbook".repeat(50);
+        let mut decoded = Vec::new();
+        flate2::read::GzDecoder::new(&gzip_bytes(&data).unwrap()[..]).read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, data);
+    }
+
+    fn empty_idempotency_cache() -> IdempotencyCache {
+        IdempotencyCache { entries: std::collections::VecDeque::new(), total_bytes: 0 }
+    }
+
+    fn cached_file(data: Vec<u8>) -> GeneratedFile {
+        GeneratedFile { data, skipped_rows: None }
+    }
+
+    #[test]
+    fn idempotency_cache_returns_stored_file() {
+        let mut cache = empty_idempotency_cache();
+        cache.insert("key-1".to_string(), 7, cached_file(vec![1, 2, 3]), 100);
+        assert_eq!(cache.get("key-1", 7).unwrap().map(|file| file.data), Some(vec![1, 2, 3]));
+        assert!(cache.get("key-2", 7).unwrap().is_none());
+    }
+
+    #[test]
+    fn idempotency_cache_rejects_key_reused_with_other_body() {
+        let mut cache = empty_idempotency_cache();
+        cache.insert("key-1".to_string(), 7, cached_file(vec![1, 2, 3]), 100);
+        assert!(matches!(cache.get("key-1", 8), Err(ExcelError::IdempotencyKeyReused(_))));
+        assert_eq!(cache.get("key-1", 7).unwrap().map(|file| file.data), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn idempotency_cache_evicts_least_recently_used() {
+        let mut cache = empty_idempotency_cache();
+        cache.insert("a".to_string(), 1, cached_file(vec![0; 4]), 10);
+        cache.insert("b".to_string(), 2, cached_file(vec![0; 4]), 10);
+        assert!(cache.get("a", 1).unwrap().is_some());
+        cache.insert("c".to_string(), 3, cached_file(vec![0; 4]), 10);
+
+        assert!(cache.get("b", 2).unwrap().is_none());
+        assert!(cache.get("a", 1).unwrap().is_some() && cache.get("c", 3).unwrap().is_some());
+        assert_eq!(cache.total_bytes, 8);
+    }
+
+    #[test]
+    fn idempotency_cache_skips_oversized_files_and_replaces_keys() {
+        let mut cache = empty_idempotency_cache();
+        cache.insert("big".to_string(), 1, cached_file(vec![0; 11]), 10);
+        assert!(cache.get("big", 1).unwrap().is_none());
+
+        cache.insert("a".to_string(), 1, cached_file(vec![1; 6]), 10);
+        cache.insert("a".to_string(), 2, cached_file(vec![2; 8]), 10);
+        assert_eq!(cache.get("a", 2).unwrap().map(|file| file.data), Some(vec![2; 8]));
+        assert_eq!(cache.total_bytes, 8);
+    }
+
+    #[test]
+    fn idempotency_cache_expires_entries() {
+        let mut cache = empty_idempotency_cache();
+        cache.insert("a".to_string(), 1, cached_file(vec![0; 4]), 10);
+        cache.evict_expired(std::time::Duration::ZERO);
+        assert!(cache.entries.is_empty());
+        assert_eq!(cache.total_bytes, 0);
+    }
+
+    #[test]
+    fn metrics_render_counts_generations_and_failures() {
+        let metrics = Metrics::new();
+        metrics.record_success(std::time::Duration::from_millis(300), 120);
+        metrics.record_success(std::time::Duration::from_secs(3), 30);
+        metrics.record_failure(&ExcelError::TooManyRows("too many".to_string()).into());
+        metrics.record_failure(&anyhow::anyhow!("disk full"));
+        let body = metrics.render();
+
+        assert!(body.contains("excel_generations_total 2\n"));
+        assert!(body.contains("excel_records_processed_total 150\n"));
+        assert!(body.contains("excel_generation_failures_total{error_code=\"TOO_MANY_ROWS\"} 1\n"));
+        assert!(body.contains("excel_generation_failures_total{error_code=\"XLSX_WRITE_FAILED\"} 1\n"));
+        assert!(body.contains("excel_generation_duration_seconds_sum 3.3\n"));
+        assert!(body.contains("excel_generation_duration_seconds_count 2\n"));
+    }
+
+    #[test]
+    fn metrics_render_uses_cumulative_buckets() {
+        let metrics = Metrics::new();
+        metrics.record_success(std::time::Duration::from_millis(50), 1);
+        metrics.record_success(std::time::Duration::from_secs(4), 1);
+        metrics.record_success(std::time::Duration::from_secs(600), 1);
+        let body = metrics.render();
+
+        assert!(body.contains("excel_generation_duration_seconds_bucket{le=\"0.1\"} 1\n"));
+        assert!(body.contains("excel_generation_duration_seconds_bucket{le=\"2.5\"} 1\n"));
+        assert!(body.contains("excel_generation_duration_seconds_bucket{le=\"5\"} 2\n"));
+        assert!(body.contains("excel_generation_duration_seconds_bucket{le=\"120\"} 2\n"));
+        assert!(body.contains("excel_generation_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+    }
+
+    #[test]
+    fn unique_entry_name_appends_counter_on_collision() {
+        let mut used = std::collections::HashSet::new();
+        assert_eq!(unique_entry_name("pegawai.xlsx", &mut used), "pegawai.xlsx");
+        assert_eq!(unique_entry_name("pegawai.xlsx", &mut used), "pegawai (2).xlsx");
+        assert_eq!(unique_entry_name("PEGAWAI.xlsx", &mut used), "PEGAWAI (3).xlsx");
+        assert_eq!(unique_entry_name("unit.csv", &mut used), "unit.csv");
+    }
+
+    #[test]
+    fn unique_entry_name_skips_names_already_taken_by_counter() {
+        let mut used = std::collections::HashSet::new();
+        assert_eq!(unique_entry_name("a (2).xlsx", &mut used), "a (2).xlsx");
+        assert_eq!(unique_entry_name("a.xlsx", &mut used), "a.xlsx");
+        assert_eq!(unique_entry_name("a.xlsx", &mut used), "a (3).xlsx");
+        assert_eq!(unique_entry_name("readme", &mut used), "readme");
+        assert_eq!(unique_entry_name("readme", &mut used), "readme (2)");
+    }
+
+    #[test]
+    fn zip_entries_keeps_names_and_contents() {
+        let zipped = zip_entries(vec![("a.xlsx".to_string(), b"first".to_vec()), ("a (2).xlsx".to_string(), b"second".to_vec())]).unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(zipped)).unwrap();
+        assert_eq!(archive.file_names().collect::<std::collections::BTreeSet<_>>(), ["a (2).xlsx", "a.xlsx"].into());
+        let mut content = String::new();
+        archive.by_name("a (2).xlsx").unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "second");
+    }
+
+    #[test]
+    fn fill_sheet_xml_rejects_row_zero() {
+        let sheet_xml = r#"<worksheet><sheetData><row r="0"><c r="A1"/></row></sheetData></worksheet>"#;
+        let result = fill_sheet_xml(sheet_xml, &sheet_spec("Sheet1", None), &ExportOptions::default());
+        assert!(matches!(result, Err(ExcelError::InvalidOptions(_))));
+    }
+
+    #[test]
+    fn bearer_token_matches_scheme_case_insensitively() {
+        assert_eq!(bearer_token("Bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("bearer abc"), Some("abc"));
+        assert_eq!(bearer_token("BEARER  abc "), Some("abc"));
+        assert_eq!(bearer_token("Basic abc"), None);
+        assert_eq!(bearer_token("Bearer"), None);
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_token() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+
+    // Test yang memakai registry jobs() dijalankan bergantian, karena finish_job menghitung hasil semua job
+    static JOB_REGISTRY_LOCK: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    fn test_job() -> std::sync::Arc<GenerationJob> {
+        std::sync::Arc::new(GenerationJob {
+            total: AtomicUsize::new(0),
+            rows_done: AtomicUsize::new(0),
+            outcome: std::sync::Mutex::new(None),
+            finished: std::sync::OnceLock::new(),
+        })
+    }
+
+    fn job_file(size: usize) -> Result<JobFile, ExcelError> {
+        Ok(JobFile { filename: "export.xlsx".to_string(), content_type: XLSX_CONTENT_TYPE, file: cached_file(vec![0; size]) })
+    }
+
+    #[tokio::test]
+    async fn async_job_streams_progress_then_done() {
+        let _registry = JOB_REGISTRY_LOCK.lock().await;
+        let req: ExportRequest = serde_json::from_value(serde_json::json!({
+            "data": [{ "a": 1 }, { "a": 2 }],
+            "options": { "filename": "job.ods", "format": "ods" }
+        }))
+        .unwrap();
+        let job_id = start_generation_job(req, "job.ods".to_string(), ODS_CONTENT_TYPE);
+
+        let response = job_progress_handler(job_id.clone()).await.unwrap().into_response();
+        let body = warp::hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let events = String::from_utf8(body.to_vec()).unwrap();
+        let progress = events.find("event:progress").unwrap();
+        let done = events.find("event:done").unwrap();
+        assert!(progress < done, "{}", events);
+        assert!(events.contains(r#""rows_done":2,"total":2"#), "{}", events);
+        assert!(events.contains(&format!("/download/{}", job_id)), "{}", events);
+    }
+
+    #[test]
+    fn finish_job_drops_oldest_results_over_limit() {
+        let _registry = JOB_REGISTRY_LOCK.blocking_lock();
+        let ids = ["finish-1", "finish-2", "finish-3"];
+        for id in ids {
+            let job = test_job();
+            jobs().insert(id.to_string(), job.clone());
+            finish_job(&job, job_file(4), 10);
+        }
+        let stored: Vec<bool> = ids.iter().map(|id| jobs().contains_key(*id)).collect();
+        assert_eq!(stored, vec![false, true, true]);
+        let newest = jobs().get("finish-3").cloned().unwrap();
+        assert!(matches!(newest.outcome.lock().unwrap().as_ref(), Some(Ok(_))));
+    }
+
+    #[test]
+    fn finish_job_rejects_result_larger_than_limit() {
+        let job = test_job();
+        finish_job(&job, job_file(11), 10);
+        assert!(job.finished.get().is_some());
+        assert!(matches!(job.outcome.lock().unwrap().as_ref(), Some(Err(ExcelError::PayloadTooLarge(_)))));
+    }
+
+    #[test]
+    fn float_scale_keeps_original_decimals() {
+        assert_eq!(float_scale(1234.0), 0);
+        assert_eq!(float_scale(1234.5), 1);
+        assert_eq!(float_scale(0.125), 3);
+        assert_eq!(float_scale(-9.99), 2);
+        assert_eq!(float_scale(1e-20), MAX_GROUPED_DECIMALS);
+    }
+
+    #[test]
+    fn validate_sheets_rejects_unknown_locale() {
+        let mut sheet = sheet_spec("Sheet1", None);
+        sheet.data = vec![serde_json::json!({ "a": 1 })];
+        let sheets = [sheet];
+        let options = |locale: &str| ExportOptions { locale: Some(locale.to_string()), ..ExportOptions::default() };
+        assert!(validate_sheets(&sheets, &options("id-id")).is_ok());
+        let err = validate_sheets(&sheets, &options("xx-XX")).unwrap_err();
+        assert!(matches!(err.downcast_ref::<ExcelError>(), Some(ExcelError::InvalidOptions(_))));
+    }
+
+    #[test]
+    fn numeric_array_requires_only_numbers() {
+        assert_eq!(numeric_array(&serde_json::json!([3, 5.5, -4])), Some(vec![3.0, 5.5, -4.0]));
+        assert_eq!(numeric_array(&serde_json::json!([3, "5"])), None);
+        assert_eq!(numeric_array(&serde_json::json!([])), None);
+        assert_eq!(numeric_array(&serde_json::json!("3,5")), None);
+    }
+
+    #[test]
+    fn sparkline_columns_places_helpers_after_data() {
+        let headers = vec!["name".to_string(), "trend".to_string()];
+        let data = vec![
+            serde_json::json!({ "name": "a", "trend": [1, 2, 3] }),
+            serde_json::json!({ "name": "b", "trend": "n/a" }),
+        ];
+        let options = ExportOptions {
+            sparkline_columns: Some(vec![SparklineSpec { field: "trend".to_string(), column: "trend".to_string() }]),
+            ..ExportOptions::default()
+        };
+        let layout = SheetLayout::new(&options);
+        let columns = sparkline_columns(&headers, &data, layout, &options).unwrap();
+        assert_eq!(columns.len(), 1);
+        assert_eq!((columns[0].col, columns[0].helper_col, columns[0].points), (1, 2, 3));
+
+        let missing = ExportOptions {
+            sparkline_columns: Some(vec![SparklineSpec { field: "trend".to_string(), column: "chart".to_string() }]),
+            ..ExportOptions::default()
+        };
+        assert!(matches!(sparkline_columns(&headers, &data, layout, &missing), Err(ExcelError::InvalidOptions(_))));
+    }
+
+    #[test]
+    fn sparkline_range_quotes_sheet_name() {
+        let sparkline = Sparkline::new("Q1 'Data'", 1, 2, 3, 1);
+        assert_eq!(sparkline.range, "'Q1 ''Data'''!$C$2:$E$2");
+        assert_eq!(sparkline.location, "B2");
+    }
+
+    #[test]
+    fn insert_sparkline_groups_appends_ext() {
+        let groups = vec![vec![Sparkline::new("Sheet1", 1, 2, 3, 1)]];
+        let xml = insert_sparkline_groups("<worksheet><sheetData/></worksheet>", &groups);
+        assert!(xml.starts_with("<worksheet><sheetData/><extLst><ext "));
+        assert!(xml.ends_with("</ext></extLst></worksheet>"));
+        assert!(xml.contains("<xm:f>'Sheet1'!$C$2:$E$2</xm:f><xm:sqref>B2</xm:sqref>"));
+
+        let existing = insert_sparkline_groups("<worksheet><extLst><ext uri=\"x\"/></extLst></worksheet>", &groups);
+        assert!(existing.starts_with("<worksheet><extLst><ext uri=\"x\"/><ext "));
+        assert_eq!(existing.matches("<extLst>").count(), 1);
+    }
+
+    #[test]
+    fn add_sparklines_rewrites_only_target_sheet() {
+        let mut writer = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for name in ["xl/worksheets/sheet1.xml", "xl/worksheets/sheet2.xml"] {
+            writer.start_file(name, zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(b"<worksheet><sheetData/></worksheet>").unwrap();
+        }
+        let xlsx = writer.finish().unwrap().into_inner();
+
+        let groups = vec![vec![Sparkline::new("Data", 1, 2, 3, 1)]];
+        let output = add_sparklines(xlsx, &[(1, groups)]).unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(output)).unwrap();
+        let mut read = |name: &str| {
+            let mut xml = String::new();
+            archive.by_name(name).unwrap().read_to_string(&mut xml).unwrap();
+            xml
+        };
+        assert_eq!(read("xl/worksheets/sheet1.xml"), "<worksheet><sheetData/></worksheet>");
+        assert!(read("xl/worksheets/sheet2.xml").contains("<x14:sparklineGroup "));
+    }
+
+    #[test]
+    fn ods_rejects_layout_options() {
+        assert!(ods_unsupported_options(&[], &ExportOptions::default()).is_empty());
+        let options = ExportOptions {
+            title: Some("Rekap".to_string()),
+            start_row: Some(2),
+            include_dictionary: Some(false),
+            null_value: Some("-".to_string()),
+            ..ExportOptions::default()
+        };
+        assert_eq!(ods_unsupported_options(&[], &options), vec!["title", "start_row"]);
+
+        let mut sheet = sheet_spec("Sheet1", None);
+        sheet.data = vec![serde_json::json!({ "a": 1 })];
+        let err = write_ods_workbook(&[sheet], &options).unwrap_err();
+        assert!(matches!(err.downcast_ref::<ExcelError>(), Some(ExcelError::InvalidOptions(_))));
+    }
+
+    #[test]
+    fn ods_rejects_xlsx_only_styling() {
+        let options = ExportOptions {
+            freeze_header: Some(true),
+            autofit: Some(true),
+            protect: Some(ProtectOptions::default()),
+            hidden_columns: Some(vec!["a".to_string()]),
+            tab_color: Some("FF0000".to_string()),
+            rtl: Some(true),
+            cell_borders: Some(true),
+            column_formats: Some(HashMap::from([("a".to_string(), "0.00".to_string())])),
+            ..ExportOptions::default()
+        };
+        assert_eq!(
+            ods_unsupported_options(&[], &options),
+            vec!["freeze_header", "autofit", "hidden_columns", "cell_borders", "column_formats", "tab_color", "rtl", "protect"]
+        );
+
+        let mut sheet = sheet_spec("Sheet1", None);
+        sheet.tab_color = Some("00FF00".to_string());
+        assert_eq!(ods_unsupported_options(&[sheet], &ExportOptions::default()), vec!["tab_color"]);
+    }
+
+    #[test]
+    fn write_ods_workbook_stores_mimetype_entry() {
+        let mut sheet = sheet_spec("Sheet1", None);
+        sheet.data = vec![serde_json::json!({ "a": 1, "b": "x" })];
+        let ods = write_ods_workbook(&[sheet], &ExportOptions::default()).unwrap();
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(ods)).unwrap();
+        let mut entry = archive.by_index(0).unwrap();
+        assert_eq!(entry.name(), "mimetype");
+        let mut mimetype = String::new();
+        entry.read_to_string(&mut mimetype).unwrap();
+        assert_eq!(mimetype, "application/vnd.oasis.opendocument.spreadsheet");
+    }
+
+    fn convert_row(record: Value, headers: &[&str], options: &ExportOptions) -> Vec<CellValue> {
+        let headers: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+        json_to_excel_row_optimized(&record, &headers, options)
+    }
+
+    #[test]
+    fn array_of_scalars_uses_array_join() {
+        let record = serde_json::json!({ "tags": ["x", "y"] });
+        let options = ExportOptions { array_join: Some(", ".to_string()), ..ExportOptions::default() };
+        assert_eq!(convert_row(record.clone(), &["tags"], &options), vec![CellValue::String("x, y".to_string())]);
+        let options = ExportOptions { array_join: Some(" | ".to_string()), ..ExportOptions::default() };
+        assert_eq!(convert_row(record, &["tags"], &options), vec![CellValue::String("x | y".to_string())]);
+    }
+
+    #[test]
+    fn auto_detect_headers_takes_union_of_keys() {
+        let data = vec![
+            serde_json::json!({ "b": 1 }),
+            serde_json::json!({ "a": 2 }),
+            serde_json::json!({ "c": 3 }),
+        ];
+        assert_eq!(auto_detect_headers(&data, true), vec!["b", "a", "c"]);
+        assert_eq!(auto_detect_headers(&data, false), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn column_types_force_numeric_nip_to_text() {
+        let options = ExportOptions {
+            column_types: Some(HashMap::from([("nip".to_string(), ColumnType::Text)])),
+            ..ExportOptions::default()
+        };
+        let row = convert_row(serde_json::json!({ "nip": 123456, "umur": 30 }), &["nip", "umur"], &options);
+        assert_eq!(row, vec![CellValue::String("123456".to_string()), CellValue::Integer(30)]);
+    }
+
+    #[test]
+    fn fit_cell_string_truncates_to_excel_limit() {
+        let long = "a".repeat(40_000);
+        let fitted = fit_cell_string(&long, 1, 0, true).unwrap();
+        assert_eq!(fitted.chars().count(), EXCEL_MAX_STRING_LEN);
+        assert!(fitted.ends_with(TRUNCATED_MARKER));
+        assert!(matches!(fit_cell_string(&long, 1, 0, false), Err(ExcelError::InvalidRecord(_))));
+        assert_eq!(fit_cell_string("short", 1, 0, false).unwrap(), "short");
+    }
+
+    #[test]
+    fn bool_format_writes_labels() {
+        let options = ExportOptions { bool_format: Some(("Ya".to_string(), "Tidak".to_string())), ..ExportOptions::default() };
+        let row = convert_row(serde_json::json!({ "aktif": true, "cuti": false }), &["aktif", "cuti"], &options);
+        assert_eq!(row, vec![CellValue::String("Ya".to_string()), CellValue::String("Tidak".to_string())]);
+    }
+
+    #[test]
+    fn null_value_fills_null_and_missing_keys() {
+        let options = ExportOptions { null_value: Some("-".to_string()), ..ExportOptions::default() };
+        let row = convert_row(serde_json::json!({ "a": null, "c": 1 }), &["a", "b", "c"], &options);
+        assert_eq!(row, vec![CellValue::String("-".to_string()), CellValue::String("-".to_string()), CellValue::Integer(1)]);
+    }
+
+    #[test]
+    fn round_decimals_rounds_floats() {
+        let options = ExportOptions {
+            round_decimals: Some(HashMap::from([("price".to_string(), 2)])),
+            ..ExportOptions::default()
+        };
+        let row = convert_row(serde_json::json!({ "price": 12.3456, "rate": 0.12345 }), &["price", "rate"], &options);
+        assert_eq!(row, vec![CellValue::Float(12.35), CellValue::Float(0.12345)]);
+    }
+
+    #[test]
+    fn inspect_data_reports_mixed_columns() {
+        let req: ExportRequest = serde_json::from_value(serde_json::json!({
+            "data": [{ "kode": 1, "nama": "A" }, { "kode": "B-2", "nama": null }],
+            "options": { "filename": "inspect.xlsx" }
+        }))
+        .unwrap();
+        let report = inspect_data(req).unwrap();
+        assert_eq!(report.records, 2);
+        let types: Vec<(&str, &str, usize)> = report
+            .columns
+            .iter()
+            .map(|column| (column.name.as_str(), column.inferred_type, column.null_count))
+            .collect();
+        assert_eq!(types, vec![("kode", "mixed", 0), ("nama", "string", 1)]);
+    }
+
+    #[test]
+    fn header_aliases_rename_titles_only() {
+        let options = ExportOptions {
+            header_aliases: Some(HashMap::from([("first_nm".to_string(), "First Name".to_string())])),
+            ..ExportOptions::default()
+        };
+        let headers = vec!["first_nm".to_string(), "age".to_string()];
+        assert_eq!(display_headers(&headers, &options).unwrap(), vec!["First Name", "age"]);
+        let row = json_to_excel_row_optimized(&serde_json::json!({ "first_nm": "Budi", "age": 30 }), &headers, &options);
+        assert_eq!(row, vec![CellValue::String("Budi".to_string()), CellValue::Integer(30)]);
+    }
+
+    #[test]
+    fn custom_headers_project_columns() {
+        let mut sheet = sheet_spec("Sheet1", None);
+        sheet.data = vec![serde_json::json!({ "a": 1, "b": 2, "c": 3 })];
+        sheet.headers = Some(vec!["c".to_string(), "a".to_string()]);
+        let headers = resolve_headers(&sheet, &ExportOptions::default());
+        assert_eq!(headers, vec!["c", "a"]);
+        let row = json_to_excel_row_optimized(&sheet.data[0], &headers, &ExportOptions::default());
+        assert_eq!(row, vec![CellValue::Integer(3), CellValue::Integer(1)]);
+    }
+
+    #[test]
+    fn normalize_arrays_expands_children_into_rows() {
+        let options = ExportOptions { normalize_arrays: Some("items".to_string()), ..ExportOptions::default() };
+        let data = vec![serde_json::json!({
+            "order": 7,
+            "items": [{ "sku": "A", "qty": 1 }, { "sku": "B", "qty": 2 }],
+            "total": 3
+        })];
+        assert_eq!(normalize_array_field(data, &options), vec![
+            serde_json::json!({ "order": 7, "items.sku": "A", "items.qty": 1, "total": 3 }),
+            serde_json::json!({ "order": 7, "items.sku": "B", "items.qty": 2, "total": 3 }),
+        ]);
+    }
+
+    #[test]
+    fn summary_sum_adds_numeric_cells() {
+        let mut sum = SummaryAccumulator::new(Aggregate::Sum);
+        for cell in [CellValue::Integer(10), CellValue::Float(2.5), CellValue::String("n/a".to_string()), CellValue::Empty] {
+            sum.add(&cell);
+        }
+        assert_eq!(sum.value(), Some(12.5));
+        assert_eq!(SummaryAccumulator::new(Aggregate::Avg).value(), None);
+    }
+
+    #[test]
+    fn validate_record_shapes_names_offending_record() {
+        let data = vec![serde_json::json!({ "a": 1 }), serde_json::json!(5)];
+        match validate_record_shapes(&data, false, None) {
+            Err(ExcelError::InvalidRecordShape(message)) => assert!(message.contains("data[1]"), "{}", message),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(validate_record_shapes(&[serde_json::json!(1), serde_json::json!("x")], false, None).is_ok());
+    }
+
+    #[test]
+    fn unpivot_records_turns_value_columns_into_rows() {
+        let spec = UnpivotSpec {
+            id_columns: vec!["id".to_string()],
+            value_columns: None,
+            variable_name: Some("bulan".to_string()),
+            value_name: None,
+        };
+        let data = vec![serde_json::json!({ "id": 1, "jan": 10, "feb": 20 })];
+        assert_eq!(unpivot_records(data, &spec), vec![
+            serde_json::json!({ "id": 1, "bulan": "jan", "value": 10 }),
+            serde_json::json!({ "id": 1, "bulan": "feb", "value": 20 }),
+        ]);
+    }
+
+    #[test]
+    fn validate_schema_reports_instance_path() {
+        let options = ExportOptions {
+            schema: Some(serde_json::json!({
+                "type": "object",
+                "properties": { "age": { "type": "integer" } }
+            })),
+            ..ExportOptions::default()
+        };
+        let data = vec![serde_json::json!({ "age": 30 }), serde_json::json!({ "age": "tiga puluh" })];
+        match validate_schema(&data, &options, None) {
+            Err(ExcelError::SchemaViolation(message)) => assert!(message.contains("data[1]/age"), "{}", message),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn missing_filename_error_names_field() {
+        let body = br#"{"data": [{"a": 1}], "options": {}}"#;
+        match decode_json_body::<ExportRequest>(None, body, 1024) {
+            Err(ExcelError::InvalidRecord(message)) => assert!(message.contains("filename"), "{}", message),
+            Err(other) => panic!("unexpected error: {:?}", other),
+            Ok(_) => panic!("body without filename was accepted"),
+        }
+    }
+
+    #[test]
+    fn numeric_columns_parse_only_listed_columns() {
+        let options = ExportOptions { numeric_columns: Some(vec!["amount".to_string()]), ..ExportOptions::default() };
+        let row = convert_row(
+            serde_json::json!({ "amount": "1500.50", "kode": "007", "qty": "12" }),
+            &["amount", "kode", "qty"],
+            &options,
+        );
+        assert_eq!(row, vec![
+            CellValue::Float(1500.5),
+            CellValue::String("007".to_string()),
+            CellValue::String("12".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn display_headers_rejects_or_dedupes_duplicates() {
+        let headers = vec!["a".to_string(), "a".to_string()];
+        assert!(matches!(display_headers(&headers, &ExportOptions::default()), Err(ExcelError::InvalidOptions(_))));
+        let options = ExportOptions { dedupe_headers: Some(true), ..ExportOptions::default() };
+        assert_eq!(display_headers(&headers, &options).unwrap(), vec!["a", "a_2"]);
+    }
+
+    fn complex_cells(mode: ComplexMode) -> (Vec<String>, Vec<CellValue>) {
+        let options = ExportOptions { complex_value_mode: Some(mode), ..ExportOptions::default() };
+        let data = normalize_records(vec![serde_json::json!({ "tags": ["x", "y"], "address": { "city": "Bandung" } })], &options);
+        let headers = auto_detect_headers(&data, true);
+        let row = json_to_excel_row_optimized(&data[0], &headers, &options);
+        (headers, row)
+    }
+
+    #[test]
+    fn complex_mode_placeholder_joins_arrays_and_hides_objects() {
+        let (headers, row) = complex_cells(ComplexMode::Placeholder);
+        assert_eq!(headers, vec!["tags", "address"]);
+        assert_eq!(row, vec![CellValue::String("x, y".to_string()), CellValue::String("[Object]".to_string())]);
+    }
+
+    #[test]
+    fn complex_mode_json_writes_compact_json() {
+        let (headers, row) = complex_cells(ComplexMode::Json);
+        assert_eq!(headers, vec!["tags", "address"]);
+        assert_eq!(row, vec![
+            CellValue::String(r#"["x","y"]"#.to_string()),
+            CellValue::String(r#"{"city":"Bandung"}"#.to_string()),
+        ]);
+    }
+
+    #[test]
+    fn complex_mode_flatten_splits_objects_into_columns() {
+        let (headers, row) = complex_cells(ComplexMode::Flatten);
+        assert_eq!(headers, vec!["tags", "address.city"]);
+        assert_eq!(row, vec![CellValue::String("x, y".to_string()), CellValue::String("Bandung".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn job_download_is_single_use() {
+        let _registry = JOB_REGISTRY_LOCK.lock().await;
+        let job = test_job();
+        jobs().insert("download-once".to_string(), job.clone());
+        finish_job(&job, job_file(4), usize::MAX);
+
+        assert!(job_download_handler("download-once".to_string()).await.is_ok());
+        let rejection = job_download_handler("download-once".to_string()).await.err().unwrap();
+        let err = rejection.find::<ExcelError>().unwrap();
+        assert!(matches!(err, ExcelError::JobNotFound(_)));
+        assert_eq!(err.status_code(), warp::http::StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn bearer_filter_passes_without_configured_token() {
+        let filter = require_bearer_token(None);
+        assert!(warp::test::request().filter(&filter).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn bearer_filter_accepts_configured_token() {
+        let filter = require_bearer_token(Some("s3cret".to_string()));
+        let result = warp::test::request().header("authorization", "Bearer s3cret").filter(&filter).await;
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn bearer_filter_rejects_wrong_or_missing_token() {
+        let filter = require_bearer_token(Some("s3cret".to_string()));
+        for request in [warp::test::request().header("authorization", "Bearer nope"), warp::test::request()] {
+            let rejection = request.filter(&filter).await.unwrap_err();
+            let err = rejection.find::<ExcelError>().unwrap();
+            assert!(matches!(err, ExcelError::Unauthorized(_)));
+            assert_eq!(err.status_code(), warp::http::StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[test]
+    fn long_integers_are_text_only_for_inferred_columns() {
+        let record = serde_json::json!({ "id": 9007199254740993u64, "nik": 3201234567890123u64 });
+        let cells = convert_row(record.clone(), &["id", "nik"], &ExportOptions::default());
+        assert_eq!(cells, vec![
+            CellValue::String("9007199254740993".to_string()),
+            CellValue::String("3201234567890123".to_string()),
+        ]);
+
+        // column_types: JSON number dan string yang sama menghasilkan cell yang sama
+        let options = ExportOptions {
+            column_types: Some(HashMap::from([("id".to_string(), ColumnType::Number), ("nik".to_string(), ColumnType::Number)])),
+            ..ExportOptions::default()
+        };
+        let from_strings = serde_json::json!({ "id": "9007199254740993", "nik": "3201234567890123" });
+        let expected = vec![CellValue::String("9007199254740993".to_string()), CellValue::Integer(3201234567890123)];
+        assert_eq!(convert_row(record, &["id", "nik"], &options), expected);
+        assert_eq!(convert_row(from_strings, &["id", "nik"], &options), expected);
+    }
+
+    #[test]
+    fn eighteen_digit_nip_keeps_exact_digits() {
+        let record = serde_json::json!({ "nip": 199001012020121001u64 });
+        let nip = vec![CellValue::String("199001012020121001".to_string())];
+        assert_eq!(convert_row(record.clone(), &["nip"], &ExportOptions::default()), nip);
+        let options = ExportOptions {
+            column_types: Some(HashMap::from([("nip".to_string(), ColumnType::Text)])),
+            ..ExportOptions::default()
+        };
+        assert_eq!(convert_row(record, &["nip"], &options), nip);
+    }
+}